@@ -0,0 +1,779 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use crate::config::{CHIP8_PROGRAM_LOAD_ADDRESS, CHIP_8_XO_MEMORY_SIZE};
+use crate::error::AssembleError;
+use crate::instruction::Instruction;
+
+// Macros may use other macros down to this depth, which stops one that uses itself
+const MAX_MACRO_DEPTH: usize = 64;
+
+// The assembled ROM, to be loaded at CHIP8_PROGRAM_LOAD_ADDRESS, and the address of every label
+#[derive(Debug, Clone, Default)]
+pub struct Assembly {
+    pub rom: Vec<u8>,
+    pub symbols: BTreeMap<String, u16>,
+}
+
+// Assemble Octo source into a ROM image. Supported are labels, `:alias`, `:const`,
+// `:macro`, `:org`, `:byte`, `if ... then`, `if ... begin ... else ... end`,
+// `loop ... while ... again`, raw sprite bytes and all CHIP-8, SUPER-CHIP and
+// XO-CHIP statements. If a `main` label exists, execution starts there.
+pub fn assemble(source: &str) -> Result<Assembly, AssembleError> {
+    let mut assembler = Assembler::new(source);
+    assembler.run()?;
+    assembler.finish()
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    line: usize,
+    // How many macro expansions deep the token came from
+    depth: usize,
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+}
+
+// A reference to a label that was not yet defined when it was used
+struct Fixup {
+    addr: usize,
+    label: String,
+    line: usize,
+    long: bool,
+}
+
+// Open `loop` and `if ... begin` blocks, holding the jumps that still need a target
+enum Block {
+    Loop { start: u16, exits: Vec<usize> },
+    Begin { pending: usize },
+    Else { pending: usize },
+}
+
+// Comparison used by `if` and `while`
+enum Condition {
+    Equal(u8, Operand),
+    NotEqual(u8, Operand),
+    Key(u8),
+    NotKey(u8),
+}
+
+#[derive(Clone, Copy)]
+enum Operand {
+    Register(u8),
+    Immediate(u8),
+}
+
+struct Assembler {
+    tokens: VecDeque<Token>,
+    memory: Vec<u8>,
+    // Next address to emit to, and the highest address written so far
+    here: usize,
+    end: usize,
+    line: usize,
+    // Macro depth of the last token read
+    depth: usize,
+    labels: HashMap<String, u16>,
+    constants: HashMap<String, i32>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    blocks: Vec<Block>,
+}
+
+impl Assembler {
+    fn new(source: &str) -> Self {
+        let mut tokens = VecDeque::new();
+        for (index, line) in source.lines().enumerate() {
+            let code = line.split('#').next().unwrap_or("");
+            for word in code.split_whitespace() {
+                tokens.push_back(Token { text: word.to_string(), line: index + 1, depth: 0 });
+            }
+        }
+
+        // Reserve the first instruction for a jump to `main` when there is one
+        let has_main = tokens.iter().zip(tokens.iter().skip(1))
+            .any(|(colon, name)| colon.text == ":" && name.text == "main");
+        let here = if has_main { CHIP8_PROGRAM_LOAD_ADDRESS + 2 } else { CHIP8_PROGRAM_LOAD_ADDRESS };
+        let mut assembler = Assembler {
+            tokens,
+            memory: vec![0; CHIP_8_XO_MEMORY_SIZE],
+            here,
+            end: here,
+            line: 1,
+            depth: 0,
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            blocks: Vec::new(),
+        };
+        if has_main {
+            assembler.fixups.push(Fixup {
+                addr: CHIP8_PROGRAM_LOAD_ADDRESS,
+                label: "main".to_string(),
+                line: 1,
+                long: false,
+            });
+            assembler.write_word(CHIP8_PROGRAM_LOAD_ADDRESS, Instruction::Jump { nnn: 0 }.encode());
+        }
+        assembler
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, AssembleError> {
+        Err(AssembleError { line: self.line, message: message.into() })
+    }
+
+    fn next(&mut self) -> Result<String, AssembleError> {
+        match self.tokens.pop_front() {
+            Some(token) => {
+                self.line = token.line;
+                self.depth = token.depth;
+                Ok(token.text)
+            },
+            None => self.error("unexpected end of file"),
+        }
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.front().map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), AssembleError> {
+        let token = self.next()?;
+        if token != expected {
+            return self.error(format!("expected '{}' but found '{}'", expected, token));
+        }
+        Ok(())
+    }
+
+    fn write_word(&mut self, addr: usize, word: u16) {
+        self.memory[addr] = (word >> 8) as u8;
+        self.memory[addr + 1] = word as u8;
+    }
+
+    fn emit_byte(&mut self, byte: u8) -> Result<(), AssembleError> {
+        if self.here >= self.memory.len() {
+            return self.error("program does not fit in memory");
+        }
+        self.memory[self.here] = byte;
+        self.here += 1;
+        self.end = self.end.max(self.here);
+        Ok(())
+    }
+
+    fn emit(&mut self, instruction: Instruction) -> Result<(), AssembleError> {
+        let word = instruction.encode();
+        self.emit_byte((word >> 8) as u8)?;
+        self.emit_byte(word as u8)
+    }
+
+    // Emit an instruction whose NNN field is the address of `target`,
+    // recording a fixup when the label is only defined later on
+    fn emit_with_address(&mut self, target: &str, build: fn(u16) -> Instruction) -> Result<(), AssembleError> {
+        let addr = match self.address(target)? {
+            Some(addr) => addr,
+            None => {
+                let fixup = Fixup { addr: self.here, label: target.to_string(), line: self.line, long: false };
+                self.fixups.push(fixup);
+                0
+            },
+        };
+        if addr > 0xFFF {
+            return self.error(format!("address {:#x} does not fit in 12 bits", addr));
+        }
+        self.emit(build(addr))
+    }
+
+    // Emit a jump with a target to be patched once it is known, returning its address
+    fn emit_placeholder_jump(&mut self) -> Result<usize, AssembleError> {
+        let addr = self.here;
+        self.emit(Instruction::Jump { nnn: 0 })?;
+        Ok(addr)
+    }
+
+    fn patch_jump(&mut self, addr: usize, target: usize) {
+        self.write_word(addr, Instruction::Jump { nnn: target as u16 }.encode());
+    }
+
+    fn is_identifier(token: &str) -> bool {
+        token.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+    }
+
+    fn number(token: &str) -> Option<i32> {
+        let (negative, digits) = match token.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, token),
+        };
+        let value = if let Some(hex) = digits.strip_prefix("0x") {
+            i32::from_str_radix(hex, 16).ok()?
+        } else if let Some(binary) = digits.strip_prefix("0b") {
+            i32::from_str_radix(binary, 2).ok()?
+        } else {
+            digits.parse::<i32>().ok()?
+        };
+        Some(if negative { -value } else { value })
+    }
+
+    // Value of a number, constant or already defined label
+    fn value(&self, token: &str) -> Option<i32> {
+        Self::number(token)
+            .or_else(|| self.constants.get(token).copied())
+            .or_else(|| self.labels.get(token).map(|&addr| addr as i32))
+    }
+
+    // Address of a label or constant, None for a label that is not defined yet
+    fn address(&self, token: &str) -> Result<Option<u16>, AssembleError> {
+        match self.value(token) {
+            Some(value) if (0..=0xFFFF).contains(&value) => Ok(Some(value as u16)),
+            Some(value) => self.error(format!("{} is not a valid address", value)),
+            None if Self::is_identifier(token) => Ok(None),
+            None => self.error(format!("expected an address but found '{}'", token)),
+        }
+    }
+
+    fn byte(&self, token: &str) -> Result<u8, AssembleError> {
+        match self.value(token) {
+            Some(value) if (-128..=255).contains(&value) => Ok(value as u8),
+            Some(value) => self.error(format!("{} does not fit in a byte", value)),
+            None => self.error(format!("expected a number but found '{}'", token)),
+        }
+    }
+
+    fn nibble(&self, token: &str) -> Result<u8, AssembleError> {
+        match self.value(token) {
+            Some(value) if (0..=0xF).contains(&value) => Ok(value as u8),
+            Some(value) => self.error(format!("{} does not fit in a nibble", value)),
+            None => self.error(format!("expected a number but found '{}'", token)),
+        }
+    }
+
+    fn register(&self, token: &str) -> Option<u8> {
+        if let Some(&register) = self.aliases.get(token) {
+            return Some(register);
+        }
+        let digit = token.strip_prefix('v').or_else(|| token.strip_prefix('V'))?;
+        if digit.len() != 1 {
+            return None;
+        }
+        u8::from_str_radix(digit, 16).ok()
+    }
+
+    fn expect_register(&mut self) -> Result<u8, AssembleError> {
+        let token = self.next()?;
+        match self.register(&token) {
+            Some(register) => Ok(register),
+            None => self.error(format!("expected a register but found '{}'", token)),
+        }
+    }
+
+    fn operand(&mut self) -> Result<Operand, AssembleError> {
+        let token = self.next()?;
+        match self.register(&token) {
+            Some(register) => Ok(Operand::Register(register)),
+            None => Ok(Operand::Immediate(self.byte(&token)?)),
+        }
+    }
+
+    fn define_label(&mut self, name: String) -> Result<(), AssembleError> {
+        if self.labels.contains_key(&name) {
+            return self.error(format!("label '{}' is defined twice", name));
+        }
+        self.labels.insert(name, self.here as u16);
+        Ok(())
+    }
+
+    fn run(&mut self) -> Result<(), AssembleError> {
+        while !self.tokens.is_empty() {
+            self.statement()?;
+        }
+        if !self.blocks.is_empty() {
+            return self.error("unterminated 'loop' or 'begin' block");
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<(), AssembleError> {
+        let token = self.next()?;
+        match token.as_str() {
+            ":" => {
+                let name = self.next()?;
+                self.define_label(name)
+            },
+            ":alias" => {
+                let name = self.next()?;
+                let register = self.expect_register()?;
+                self.aliases.insert(name, register);
+                Ok(())
+            },
+            ":const" => {
+                let name = self.next()?;
+                let token = self.next()?;
+                match self.value(&token) {
+                    Some(value) => {
+                        self.constants.insert(name, value);
+                        Ok(())
+                    },
+                    None => self.error(format!("'{}' is not a constant value", token)),
+                }
+            },
+            ":macro" => self.define_macro(),
+            ":org" => {
+                let token = self.next()?;
+                match self.address(&token)? {
+                    Some(addr) if addr as usize >= CHIP8_PROGRAM_LOAD_ADDRESS => {
+                        self.here = addr as usize;
+                        Ok(())
+                    },
+                    _ => self.error(format!("invalid origin '{}'", token)),
+                }
+            },
+            ":byte" => {
+                let token = self.next()?;
+                let byte = self.byte(&token)?;
+                self.emit_byte(byte)
+            },
+            ":call" => {
+                let target = self.next()?;
+                self.emit_with_address(&target, |nnn| Instruction::Call { nnn })
+            },
+            ";" | "return" => self.emit(Instruction::Ret),
+            "clear" => self.emit(Instruction::Cls),
+            "exit" => self.emit(Instruction::Exit),
+            "hires" => self.emit(Instruction::Hires),
+            "lores" => self.emit(Instruction::Lores),
+            "scroll-left" => self.emit(Instruction::ScrollLeft),
+            "scroll-right" => self.emit(Instruction::ScrollRight),
+            "scroll-down" => {
+                let token = self.next()?;
+                let n = self.nibble(&token)?;
+                self.emit(Instruction::ScrollDown { n })
+            },
+            "scroll-up" => {
+                let token = self.next()?;
+                let n = self.nibble(&token)?;
+                self.emit(Instruction::ScrollUp { n })
+            },
+            "plane" => {
+                let token = self.next()?;
+                let n = self.nibble(&token)?;
+                self.emit(Instruction::SelectPlanes { n })
+            },
+            "audio" => self.emit(Instruction::LoadAudio),
+            "bcd" => {
+                let x = self.expect_register()?;
+                self.emit(Instruction::Bcd { x })
+            },
+            "save" | "load" => self.save_load(&token),
+            "saveflags" => {
+                let x = self.expect_register()?;
+                self.emit(Instruction::StoreFlags { x })
+            },
+            "loadflags" => {
+                let x = self.expect_register()?;
+                self.emit(Instruction::LoadFlags { x })
+            },
+            "sprite" => {
+                let x = self.expect_register()?;
+                let y = self.expect_register()?;
+                let token = self.next()?;
+                let n = self.nibble(&token)?;
+                self.emit(Instruction::Draw { x, y, n })
+            },
+            "jump" => {
+                let target = self.next()?;
+                self.emit_with_address(&target, |nnn| Instruction::Jump { nnn })
+            },
+            "jump0" => {
+                let target = self.next()?;
+                self.emit_with_address(&target, |nnn| Instruction::JumpOffset { nnn })
+            },
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.expect_register()?;
+                match token.as_str() {
+                    "delay" => self.emit(Instruction::SetDelay { x }),
+                    "buzzer" => self.emit(Instruction::SetSound { x }),
+                    _ => self.emit(Instruction::Pitch { x }),
+                }
+            },
+            "i" => self.index_statement(),
+            "if" => self.if_statement(),
+            "else" => match self.blocks.pop() {
+                Some(Block::Begin { pending }) => {
+                    let skip_else = self.emit_placeholder_jump()?;
+                    self.patch_jump(pending, self.here);
+                    self.blocks.push(Block::Else { pending: skip_else });
+                    Ok(())
+                },
+                _ => self.error("'else' without 'begin'"),
+            },
+            "end" => match self.blocks.pop() {
+                Some(Block::Begin { pending }) | Some(Block::Else { pending }) => {
+                    self.patch_jump(pending, self.here);
+                    Ok(())
+                },
+                _ => self.error("'end' without 'begin'"),
+            },
+            "loop" => {
+                self.blocks.push(Block::Loop { start: self.here as u16, exits: Vec::new() });
+                Ok(())
+            },
+            "while" => {
+                let condition = self.condition()?;
+                self.emit_skip_if(&condition, true)?;
+                let exit = self.emit_placeholder_jump()?;
+                match self.blocks.iter_mut().rev().find(|block| matches!(block, Block::Loop { .. })) {
+                    Some(Block::Loop { exits, .. }) => {
+                        exits.push(exit);
+                        Ok(())
+                    },
+                    _ => self.error("'while' outside of a loop"),
+                }
+            },
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop { start, exits }) => {
+                    self.emit(Instruction::Jump { nnn: start })?;
+                    for exit in exits {
+                        self.patch_jump(exit, self.here);
+                    }
+                    Ok(())
+                },
+                _ => self.error("'again' without 'loop'"),
+            },
+            _ => self.other_statement(token),
+        }
+    }
+
+    fn other_statement(&mut self, token: String) -> Result<(), AssembleError> {
+        if let Some(x) = self.register(&token) {
+            return self.register_statement(x);
+        }
+        if self.macros.contains_key(&token) {
+            return self.expand_macro(&token);
+        }
+        if let Some(value) = Self::number(&token).or_else(|| self.constants.get(&token).copied()) {
+            // Bare numbers are raw data, typically sprites
+            if !(-128..=255).contains(&value) {
+                return self.error(format!("{} does not fit in a byte", value));
+            }
+            return self.emit_byte(value as u8);
+        }
+        if Self::is_identifier(&token) {
+            // Any other name is a call to the subroutine with that label
+            return self.emit_with_address(&token, |nnn| Instruction::Call { nnn });
+        }
+        self.error(format!("unexpected '{}'", token))
+    }
+
+    fn save_load(&mut self, token: &str) -> Result<(), AssembleError> {
+        let x = self.expect_register()?;
+        if self.peek() == Some("-") {
+            self.next()?;
+            let y = self.expect_register()?;
+            return match token {
+                "save" => self.emit(Instruction::StoreRange { x, y }),
+                _ => self.emit(Instruction::LoadRange { x, y }),
+            };
+        }
+        match token {
+            "save" => self.emit(Instruction::Store { x }),
+            _ => self.emit(Instruction::Load { x }),
+        }
+    }
+
+    fn index_statement(&mut self) -> Result<(), AssembleError> {
+        let op = self.next()?;
+        match op.as_str() {
+            "+=" => {
+                let x = self.expect_register()?;
+                self.emit(Instruction::AddI { x })
+            },
+            ":=" => {
+                let token = self.next()?;
+                match token.as_str() {
+                    "hex" => {
+                        let x = self.expect_register()?;
+                        self.emit(Instruction::Font { x })
+                    },
+                    "bighex" => {
+                        let x = self.expect_register()?;
+                        self.emit(Instruction::BigFont { x })
+                    },
+                    "long" => {
+                        let target = self.next()?;
+                        self.emit(Instruction::LongSetI)?;
+                        let addr = match self.address(&target)? {
+                            Some(addr) => addr,
+                            None => {
+                                let fixup = Fixup { addr: self.here, label: target, line: self.line, long: true };
+                                self.fixups.push(fixup);
+                                0
+                            },
+                        };
+                        self.emit_byte((addr >> 8) as u8)?;
+                        self.emit_byte(addr as u8)
+                    },
+                    _ => self.emit_with_address(&token, |nnn| Instruction::SetI { nnn }),
+                }
+            },
+            _ => self.error(format!("unknown operator '{}' for i", op)),
+        }
+    }
+
+    fn register_statement(&mut self, x: u8) -> Result<(), AssembleError> {
+        let op = self.next()?;
+        if op == ":=" {
+            match self.peek() {
+                Some("key") => {
+                    self.next()?;
+                    return self.emit(Instruction::WaitKey { x });
+                },
+                Some("delay") => {
+                    self.next()?;
+                    return self.emit(Instruction::GetDelay { x });
+                },
+                Some("random") => {
+                    self.next()?;
+                    let token = self.next()?;
+                    let nn = self.byte(&token)?;
+                    return self.emit(Instruction::Random { x, nn });
+                },
+                _ => (),
+            }
+        }
+
+        let instruction = match (op.as_str(), self.operand()?) {
+            (":=", Operand::Immediate(nn)) => Instruction::SetImm { x, nn },
+            (":=", Operand::Register(y)) => Instruction::Set { x, y },
+            ("+=", Operand::Immediate(nn)) => Instruction::AddImm { x, nn },
+            ("+=", Operand::Register(y)) => Instruction::Add { x, y },
+            ("-=", Operand::Immediate(nn)) => Instruction::AddImm { x, nn: nn.wrapping_neg() },
+            ("-=", Operand::Register(y)) => Instruction::Sub { x, y },
+            ("=-", Operand::Register(y)) => Instruction::SubN { x, y },
+            ("|=", Operand::Register(y)) => Instruction::Or { x, y },
+            ("&=", Operand::Register(y)) => Instruction::And { x, y },
+            ("^=", Operand::Register(y)) => Instruction::Xor { x, y },
+            (">>=", Operand::Register(y)) => Instruction::Shr { x, y },
+            ("<<=", Operand::Register(y)) => Instruction::Shl { x, y },
+            _ => return self.error(format!("invalid operands for '{}'", op)),
+        };
+        self.emit(instruction)
+    }
+
+    fn condition(&mut self) -> Result<Condition, AssembleError> {
+        let x = self.expect_register()?;
+        let op = self.next()?;
+        match op.as_str() {
+            "==" => Ok(Condition::Equal(x, self.operand()?)),
+            "!=" => Ok(Condition::NotEqual(x, self.operand()?)),
+            "key" => Ok(Condition::Key(x)),
+            "-key" => Ok(Condition::NotKey(x)),
+            _ => self.error(format!("unsupported comparison '{}'", op)),
+        }
+    }
+
+    // Emit the skip instruction that jumps over the next one when `condition` is
+    // `when`. `if ... then` skips its statement when the condition is false.
+    fn emit_skip_if(&mut self, condition: &Condition, when: bool) -> Result<(), AssembleError> {
+        let equal = match condition {
+            Condition::Equal(..) | Condition::Key(_) => when,
+            Condition::NotEqual(..) | Condition::NotKey(_) => !when,
+        };
+        let instruction = match condition {
+            Condition::Equal(x, operand) | Condition::NotEqual(x, operand) => match (*operand, equal) {
+                (Operand::Immediate(nn), true) => Instruction::SkipEqImm { x: *x, nn },
+                (Operand::Immediate(nn), false) => Instruction::SkipNeImm { x: *x, nn },
+                (Operand::Register(y), true) => Instruction::SkipEq { x: *x, y },
+                (Operand::Register(y), false) => Instruction::SkipNe { x: *x, y },
+            },
+            Condition::Key(x) | Condition::NotKey(x) => match equal {
+                true => Instruction::SkipKey { x: *x },
+                false => Instruction::SkipNotKey { x: *x },
+            },
+        };
+        self.emit(instruction)
+    }
+
+    fn if_statement(&mut self) -> Result<(), AssembleError> {
+        let condition = self.condition()?;
+        let keyword = self.next()?;
+        match keyword.as_str() {
+            "then" => self.emit_skip_if(&condition, false),
+            "begin" => {
+                self.emit_skip_if(&condition, true)?;
+                let pending = self.emit_placeholder_jump()?;
+                self.blocks.push(Block::Begin { pending });
+                Ok(())
+            },
+            _ => self.error(format!("expected 'then' or 'begin' but found '{}'", keyword)),
+        }
+    }
+
+    fn define_macro(&mut self) -> Result<(), AssembleError> {
+        let name = self.next()?;
+        let mut params = Vec::new();
+        loop {
+            let token = self.next()?;
+            if token == "{" {
+                break;
+            }
+            params.push(token);
+        }
+
+        let mut body = Vec::new();
+        let mut depth = 1;
+        loop {
+            let token = self.next()?;
+            match token.as_str() {
+                "{" => depth += 1,
+                "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                },
+                _ => (),
+            }
+            body.push(Token { text: token, line: self.line, depth: 0 });
+        }
+        self.macros.insert(name, Macro { params, body });
+        Ok(())
+    }
+
+    // Substitute the arguments into the macro body and push it back onto the token stream
+    fn expand_macro(&mut self, name: &str) -> Result<(), AssembleError> {
+        let depth = self.depth + 1;
+        if depth > MAX_MACRO_DEPTH {
+            return self.error(format!("macro '{}' is nested more than {} deep", name, MAX_MACRO_DEPTH));
+        }
+        let param_count = self.macros[name].params.len();
+        let mut args = HashMap::new();
+        for index in 0..param_count {
+            let arg = self.next()?;
+            args.insert(self.macros[name].params[index].clone(), arg);
+        }
+        let line = self.line;
+        let expanded: Vec<Token> = self.macros[name].body.iter()
+            .map(|token| Token {
+                text: args.get(&token.text).cloned().unwrap_or_else(|| token.text.clone()),
+                line,
+                depth,
+            })
+            .collect();
+        for token in expanded.into_iter().rev() {
+            self.tokens.push_front(token);
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Assembly, AssembleError> {
+        for fixup in std::mem::take(&mut self.fixups) {
+            let addr = match self.labels.get(&fixup.label) {
+                Some(&addr) => addr,
+                None => {
+                    let message = format!("undefined label '{}'", fixup.label);
+                    return Err(AssembleError { line: fixup.line, message });
+                },
+            };
+            if fixup.long {
+                self.write_word(fixup.addr, addr);
+            } else {
+                if addr > 0xFFF {
+                    let message = format!("label '{}' at {:#x} is out of 12 bit range", fixup.label, addr);
+                    return Err(AssembleError { line: fixup.line, message });
+                }
+                let op = (self.memory[fixup.addr] as u16) << 8 | self.memory[fixup.addr + 1] as u16;
+                self.write_word(fixup.addr, (op & 0xF000) | addr);
+            }
+        }
+
+        let rom = self.memory[CHIP8_PROGRAM_LOAD_ADDRESS..self.end].to_vec();
+        let symbols = self.labels.into_iter().collect();
+        Ok(Assembly { rom, symbols })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rom(source: &str) -> Vec<u8> {
+        assemble(source).unwrap().rom
+    }
+
+    #[test]
+    fn labels_and_forward_references() {
+        let source = "
+            : main
+                v0 := 1
+                jump end
+            : end
+                jump end
+        ";
+        assert_eq!(rom(source), [0x12, 0x02, 0x60, 0x01, 0x12, 0x06, 0x12, 0x06]);
+        assert_eq!(assemble(source).unwrap().symbols["end"], 0x206);
+    }
+
+    #[test]
+    fn calls_to_later_subroutines() {
+        assert_eq!(rom(": start sub ; : sub v1 += 2 ;"), [0x22, 0x04, 0x00, 0xEE, 0x71, 0x02, 0x00, 0xEE]);
+    }
+
+    #[test]
+    fn undefined_label() {
+        let err = assemble("\n jump nowhere").unwrap_err();
+        assert_eq!(err.line, 2);
+    }
+
+    #[test]
+    fn if_then_skips_when_false() {
+        assert_eq!(rom("if v0 == 5 then v1 := 2"), [0x40, 0x05, 0x61, 0x02]);
+        assert_eq!(rom("if v0 != v3 then v1 := 2"), [0x50, 0x30, 0x61, 0x02]);
+        assert_eq!(rom("if v2 key then clear"), [0xE2, 0xA1, 0x00, 0xE0]);
+    }
+
+    #[test]
+    fn if_begin_else_end() {
+        let source = "if v0 == 1 begin v1 := 1 else v1 := 2 end";
+        assert_eq!(rom(source), [0x30, 0x01, 0x12, 0x08, 0x61, 0x01, 0x12, 0x0A, 0x61, 0x02]);
+    }
+
+    #[test]
+    fn loop_while_again() {
+        let source = "
+            loop
+                v0 += 1
+                while v0 != 10
+            again
+        ";
+        assert_eq!(rom(source), [0x70, 0x01, 0x40, 0x0A, 0x12, 0x08, 0x12, 0x00]);
+    }
+
+    #[test]
+    fn macros_substitute_arguments() {
+        let source = ":macro add-both a b { a += b b += a } add-both v1 v2";
+        assert_eq!(rom(source), [0x81, 0x24, 0x82, 0x14]);
+    }
+
+    #[test]
+    fn recursive_macro_is_an_error() {
+        let err = assemble(":macro foo { foo }\nfoo").unwrap_err();
+        assert_eq!(err.line, 2);
+        assert!(err.message.contains("foo"));
+    }
+
+    #[test]
+    fn constants() {
+        assert_eq!(rom(":const speed 3 v0 := speed v1 += speed"), [0x60, 0x03, 0x71, 0x03]);
+    }
+
+    #[test]
+    fn long_index() {
+        assert_eq!(rom("i := long data : data 0xFF"), [0xF0, 0x00, 0x02, 0x04, 0xFF]);
+        assert_eq!(rom(": data 1 i := long data"), [0x01, 0xF0, 0x00, 0x02, 0x00]);
+        assert_eq!(rom(":org 0x3000 : far 2 :org 0x201 i := long far")[1..5], [0xF0, 0x00, 0x30, 0x00]);
+    }
+}
@@ -0,0 +1,456 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::chip8::Chip8;
+use crate::config::{CHIP_8_AUDIO_SAMPLE_RATE, CHIP_8_HIRES_HEIGHT, CHIP_8_HIRES_WIDTH, CHIP_8_TIMER_HZ};
+use crate::filter::{self, Filter, Image};
+use crate::frontend::AudioSettings;
+use crate::screen::Chip8Screen;
+use crate::synth::Synth;
+
+// Audio samples recorded per 60 Hz frame
+const SAMPLES_PER_FRAME: u32 = CHIP_8_AUDIO_SAMPLE_RATE / CHIP_8_TIMER_HZ;
+
+// Shortest GIF frame delay in hundredths of a second. Viewers show anything faster
+// than this much slower instead, so frames shown for less time are dropped.
+const GIF_MIN_DELAY: u32 = 2;
+// NeuQuant speed for frames whose colours aren't all in the palette, 1 (best) to 30
+const GIF_QUANTIZE_SPEED: i32 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoFormat {
+    // Animated GIF using the palette, playable anywhere. Filters that add colours make
+    // it fall back to a palette chosen for each frame.
+    Gif,
+    // Uncompressed YUV4MPEG2, for ffmpeg -i clip.y4m
+    Y4m,
+    // Back to back binary PPM images, for ffmpeg -f image2pipe -framerate 60 -i clip.ppm
+    Ppm,
+}
+
+impl VideoFormat {
+    pub fn from_path(path: &Path) -> Option<VideoFormat> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "gif" => Some(VideoFormat::Gif),
+            "y4m" => Some(VideoFormat::Y4m),
+            "ppm" => Some(VideoFormat::Ppm),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            VideoFormat::Gif => "gif",
+            VideoFormat::Y4m => "y4m",
+            VideoFormat::Ppm => "ppm",
+        }
+    }
+}
+
+// Records a clip of gameplay: the screen at the end of every 60 Hz frame and the
+// sound timer's audio over that frame. Frames are always 128x64 pixels times the
+// filters' and the capture's scale, so switching resolution mid clip works; low
+// resolution pixels are doubled after filtering.
+pub struct Capture {
+    video: Option<VideoWriter>,
+    palette: [[u8; 3]; 4],
+    filters: Vec<Filter>,
+    scale: usize,
+    wav: Option<WavWriter>,
+    synth: Synth,
+    frames: u32,
+}
+
+impl Capture {
+    pub fn new(
+        video: Option<(File, VideoFormat)>,
+        wav: Option<File>,
+        palette: [[u8; 3]; 4],
+        filters: Vec<Filter>,
+        scale: usize,
+        audio: AudioSettings,
+    ) -> io::Result<Capture> {
+        let size = filter::scale(&filters) * scale;
+        let (width, height) = (CHIP_8_HIRES_WIDTH * size, CHIP_8_HIRES_HEIGHT * size);
+        let video = match video {
+            Some((file, format)) => Some(VideoWriter::new(BufWriter::new(file), format, palette, width, height)?),
+            None => None,
+        };
+        let wav = match wav {
+            Some(file) => Some(WavWriter::new(BufWriter::new(file))?),
+            None => None,
+        };
+        Ok(Capture {
+            video,
+            palette,
+            filters,
+            scale,
+            wav,
+            synth: Synth::new(audio, CHIP_8_AUDIO_SAMPLE_RATE),
+            frames: 0,
+        })
+    }
+
+    // Record one frame. Call it once per frame after the frame's instructions have
+    // run and before the timers tick, so the tone matches what the frame plays.
+    pub fn frame(&mut self, chip8: &Chip8) -> io::Result<()> {
+        if let Some(video) = self.video.as_mut() {
+            video.frame(&render(chip8.get_display(), &self.palette, &self.filters, self.scale))?;
+        }
+        if let Some(wav) = self.wav.as_mut() {
+            self.synth.set_tone(chip8.tone());
+            for _ in 0..SAMPLES_PER_FRAME {
+                wav.sample(self.synth.next_sample())?;
+            }
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    // Write out anything pending and fill in the headers
+    pub fn finish(self) -> io::Result<()> {
+        if let Some(video) = self.video {
+            video.finish()?;
+        }
+        if let Some(wav) = self.wav {
+            wav.finish()?;
+        }
+        Ok(())
+    }
+}
+
+// The whole frame at the fixed capture size
+fn render(screen: &Chip8Screen, palette: &[[u8; 3]; 4], filters: &[Filter], scale: usize) -> Image {
+    let image = filter::render(screen, palette, filters);
+    let scale = if screen.hires { scale } else { scale * 2 };
+    image.resize(scale)
+}
+
+enum VideoWriter {
+    Gif(GifWriter),
+    Raw { writer: BufWriter<File>, format: VideoFormat },
+}
+
+impl VideoWriter {
+    fn new(mut writer: BufWriter<File>, format: VideoFormat, palette: [[u8; 3]; 4], width: usize, height: usize) -> io::Result<VideoWriter> {
+        match format {
+            VideoFormat::Gif => Ok(VideoWriter::Gif(GifWriter::new(writer, palette, width, height)?)),
+            VideoFormat::Y4m => {
+                writeln!(writer, "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444", width, height, CHIP_8_TIMER_HZ)?;
+                Ok(VideoWriter::Raw { writer, format })
+            },
+            VideoFormat::Ppm => Ok(VideoWriter::Raw { writer, format }),
+        }
+    }
+
+    fn frame(&mut self, image: &Image) -> io::Result<()> {
+        match self {
+            VideoWriter::Gif(gif) => gif.frame(image),
+            VideoWriter::Raw { writer, format: VideoFormat::Y4m } => {
+                let colours: Vec<[u8; 3]> = image.pixels.iter().map(|&colour| ycbcr(colour)).collect();
+                writer.write_all(b"FRAME\n")?;
+                // Y, Cb and Cr planes one after the other
+                for component in 0..3 {
+                    let data: Vec<u8> = colours.iter().map(|colour| colour[component]).collect();
+                    writer.write_all(&data)?;
+                }
+                Ok(())
+            },
+            VideoWriter::Raw { writer, .. } => {
+                write!(writer, "P6\n{} {}\n255\n", image.width, image.height)?;
+                writer.write_all(&image.to_rgb())
+            },
+        }
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self {
+            VideoWriter::Gif(gif) => gif.finish(),
+            VideoWriter::Raw { mut writer, .. } => writer.flush(),
+        }
+    }
+}
+
+// BT.601 studio range, as ffmpeg expects from Y4M by default
+fn ycbcr([r, g, b]: [u8; 3]) -> [u8; 3] {
+    let (r, g, b) = (r as f32, g as f32, b as f32);
+    let y = 16.0 + (65.481 * r + 128.553 * g + 24.966 * b) / 255.0;
+    let cb = 128.0 + (-37.797 * r - 74.203 * g + 112.0 * b) / 255.0;
+    let cr = 128.0 + (112.0 * r - 93.786 * g - 18.214 * b) / 255.0;
+    [y.round() as u8, cb.round() as u8, cr.round() as u8]
+}
+
+// GIF frames only hold the area that changed since the previous one, and a frame
+// isn't written until the next change so it can be given the time it stayed up.
+struct GifWriter {
+    encoder: gif::Encoder<BufWriter<File>>,
+    palette: [[u8; 3]; 4],
+    width: usize,
+    // What the viewer shows after the frames written so far
+    shown: Vec<[u8; 3]>,
+    // Frame waiting to be written and the time it went up, in hundredths of a second
+    pending: Option<(Vec<[u8; 3]>, u32)>,
+    // 60 Hz frames seen so far
+    frames: u32,
+}
+
+impl GifWriter {
+    fn new(writer: BufWriter<File>, palette: [[u8; 3]; 4], width: usize, height: usize) -> io::Result<GifWriter> {
+        if width > u16::MAX as usize || height > u16::MAX as usize {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "scale too large for a GIF"));
+        }
+        let mut encoder = gif::Encoder::new(writer, width as u16, height as u16, palette.as_flattened())
+            .map_err(gif_error)?;
+        encoder.set_repeat(gif::Repeat::Infinite).map_err(gif_error)?;
+        Ok(GifWriter {
+            encoder,
+            palette,
+            width,
+            shown: Vec::new(),
+            pending: None,
+            frames: 0,
+        })
+    }
+
+    // When a 60 Hz frame starts, in hundredths of a second
+    fn time(frame: u32) -> u32 {
+        (frame as u64 * 100 / CHIP_8_TIMER_HZ as u64) as u32
+    }
+
+    fn frame(&mut self, image: &Image) -> io::Result<()> {
+        let image = image.pixels.clone();
+        let now = GifWriter::time(self.frames);
+        self.frames += 1;
+        match self.pending.take() {
+            None => self.pending = Some((image, now)),
+            Some((pending, start)) if pending == image => self.pending = Some((pending, start)),
+            // Too short to show, replace it with the new frame
+            Some((_, start)) if now - start < GIF_MIN_DELAY => self.pending = Some((image, start)),
+            Some((pending, start)) => {
+                self.write(pending, now - start)?;
+                self.pending = Some((image, now));
+            },
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, image: Vec<[u8; 3]>, delay: u32) -> io::Result<()> {
+        let width = self.width;
+        // Bounds of the changed area, or the whole image for the first frame
+        let (mut left, mut top, mut right, mut bottom) = (0, 0, width, image.len() / width);
+        if !self.shown.is_empty() {
+            let changed = || image.iter().zip(&self.shown).enumerate().filter(|(_, (new, old))| new != old).map(|(i, _)| i);
+            left = changed().map(|i| i % width).min().unwrap_or(0);
+            right = changed().map(|i| i % width + 1).max().unwrap_or(1);
+            top = changed().map(|i| i / width).min().unwrap_or(0);
+            bottom = changed().map(|i| i / width + 1).max().unwrap_or(1);
+        }
+        let mut changed = Vec::with_capacity((right - left) * (bottom - top));
+        for row in image.chunks(width).take(bottom).skip(top) {
+            changed.extend_from_slice(&row[left..right]);
+        }
+        let (frame_width, frame_height) = ((right - left) as u16, (bottom - top) as u16);
+
+        // Indexes into the global palette when every colour is in it, otherwise a
+        // palette of its own for this frame
+        let indexes: Option<Vec<u8>> = changed.iter()
+            .map(|colour| self.palette.iter().position(|entry| entry == colour).map(|index| index as u8))
+            .collect();
+        let frame = match indexes {
+            Some(buffer) => gif::Frame {
+                width: frame_width,
+                height: frame_height,
+                buffer: buffer.into(),
+                ..gif::Frame::default()
+            },
+            None => gif::Frame::from_rgb_speed(frame_width, frame_height, changed.as_flattened(), GIF_QUANTIZE_SPEED),
+        };
+        let frame = gif::Frame {
+            left: left as u16,
+            top: top as u16,
+            delay: delay.min(u16::MAX as u32) as u16,
+            ..frame
+        };
+        self.encoder.write_frame(&frame).map_err(gif_error)?;
+        self.shown = image;
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        if let Some((image, start)) = self.pending.take() {
+            let delay = (GifWriter::time(self.frames) - start).max(GIF_MIN_DELAY);
+            self.write(image, delay)?;
+        }
+        let mut writer = self.encoder.into_inner()?;
+        writer.flush()
+    }
+}
+
+fn gif_error(err: gif::EncodingError) -> io::Error {
+    match err {
+        gif::EncodingError::Io(err) => err,
+        err => io::Error::other(err.to_string()),
+    }
+}
+
+// 16 bit mono PCM WAV. The sizes in the header are filled in by `finish`.
+struct WavWriter {
+    writer: BufWriter<File>,
+    samples: u32,
+}
+
+impl WavWriter {
+    fn new(mut writer: BufWriter<File>) -> io::Result<WavWriter> {
+        let rate = CHIP_8_AUDIO_SAMPLE_RATE;
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all(b"WAVEfmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?;
+        writer.write_all(&1u16.to_le_bytes())?; // PCM
+        writer.write_all(&1u16.to_le_bytes())?; // mono
+        writer.write_all(&rate.to_le_bytes())?;
+        writer.write_all(&(rate * 2).to_le_bytes())?; // bytes per second
+        writer.write_all(&2u16.to_le_bytes())?; // bytes per sample
+        writer.write_all(&16u16.to_le_bytes())?; // bits per sample
+        writer.write_all(b"data")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        Ok(WavWriter { writer, samples: 0 })
+    }
+
+    fn sample(&mut self, sample: f32) -> io::Result<()> {
+        let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        self.samples += 1;
+        self.writer.write_all(&value.to_le_bytes())
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        let data_size = self.samples * 2;
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer.write_all(&(36 + data_size).to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(40))?;
+        self.writer.write_all(&data_size.to_le_bytes())?;
+        self.writer.flush()
+    }
+}
+
+// Create `<base>-<YYYYMMDD-HHMMSS>.<extension>` using the current UTC time, counting
+// up if that name was already taken this second. Returns the file and its path.
+pub fn create_timestamped(base: &Path, extension: &str) -> io::Result<(File, PathBuf)> {
+    let mut name = base.as_os_str().to_owned();
+    name.push(format!("-{}", timestamp(SystemTime::now())));
+    let mut count = 1;
+    loop {
+        let mut path = name.clone();
+        if count > 1 {
+            path.push(format!("-{}", count));
+        }
+        path.push(format!(".{}", extension));
+        let path = PathBuf::from(path);
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((file, path)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => count += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// YYYYMMDD-HHMMSS in UTC
+fn timestamp(time: SystemTime) -> String {
+    let seconds = time.duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs());
+    let (days, seconds) = (seconds / 86400, seconds % 86400);
+
+    // Days since 1970-01-01 to a civil date, counting in 400 year eras from March 0000
+    let days = days as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+
+    format!("{:04}{:02}{:02}-{:02}{:02}{:02}", year, month, day, seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn at(seconds: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(seconds)
+    }
+
+    // A file in the temporary directory, removed again by the caller
+    fn temp_file(name: &str) -> (PathBuf, BufWriter<File>) {
+        let path = std::env::temp_dir().join(format!("chip-8-test-{}-{}", std::process::id(), name));
+        let file = File::create(&path).unwrap();
+        (path, BufWriter::new(file))
+    }
+
+    fn read_back(path: &Path) -> Vec<u8> {
+        let data = std::fs::read(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        data
+    }
+
+    #[test]
+    fn timestamps_are_utc_dates() {
+        assert_eq!(timestamp(UNIX_EPOCH), "19700101-000000");
+        assert_eq!(timestamp(at(1_709_164_799)), "20240228-235959");
+        assert_eq!(timestamp(at(1_709_210_096)), "20240229-123456");
+        assert_eq!(timestamp(at(1_709_251_200)), "20240301-000000");
+        // 2000 is a leap year despite being a century
+        assert_eq!(timestamp(at(951_782_400)), "20000229-000000");
+    }
+
+    #[test]
+    fn y4m_header_and_frame() {
+        let (path, writer) = temp_file("header.y4m");
+        let mut video = VideoWriter::new(writer, VideoFormat::Y4m, [[0; 3]; 4], 4, 2).unwrap();
+        video.frame(&Image::new(4, 2, vec![[255, 255, 255]; 8])).unwrap();
+        video.finish().unwrap();
+
+        let data = read_back(&path);
+        let header = b"YUV4MPEG2 W4 H2 F60:1 Ip A1:1 C444\nFRAME\n";
+        assert_eq!(&data[..header.len()], header);
+        // White is 235 luma and neutral chroma in studio range
+        let planes = &data[header.len()..];
+        assert_eq!(planes.len(), 4 * 2 * 3);
+        assert!(planes[..8].iter().all(|&y| y == 235));
+        assert!(planes[8..].iter().all(|&c| c == 128));
+    }
+
+    #[test]
+    fn wav_header_sizes_are_filled_in() {
+        let (path, writer) = temp_file("header.wav");
+        let mut wav = WavWriter::new(writer).unwrap();
+        for sample in [0.0, 1.0, -1.0] {
+            wav.sample(sample).unwrap();
+        }
+        wav.finish().unwrap();
+
+        let data = read_back(&path);
+        let u32_at = |offset: usize| u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+        let u16_at = |offset: usize| u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap());
+        assert_eq!(data.len(), 44 + 6);
+        assert_eq!(&data[..4], b"RIFF");
+        assert_eq!(u32_at(4), 36 + 6);
+        assert_eq!(&data[8..16], b"WAVEfmt ");
+        assert_eq!(u16_at(20), 1);
+        assert_eq!(u16_at(22), 1);
+        assert_eq!(u32_at(24), CHIP_8_AUDIO_SAMPLE_RATE);
+        assert_eq!(u32_at(28), CHIP_8_AUDIO_SAMPLE_RATE * 2);
+        assert_eq!(u16_at(34), 16);
+        assert_eq!(&data[36..40], b"data");
+        assert_eq!(u32_at(40), 6);
+        assert_eq!(u16_at(46) as i16, i16::MAX);
+        assert_eq!(u16_at(48) as i16, -i16::MAX);
+    }
+}
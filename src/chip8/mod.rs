@@ -3,9 +3,27 @@ use crate::registers::Chip8Regsiters;
 use crate::stack::Chip8Stack;
 use crate::keyboard::Chip8Keyboard;
 use crate::screen::Chip8Screen;
-use crate::sound::beep;
-use crate::config::{CHIP8_PROGRAM_LOAD_ADDRESS, CHIP_8_MEMORY_SIZE, CHIP_8_TOTAL_KEYS, CHIP8_DEFAULT_SPRITE_HEIGHT};
-use rand::Rng;
+use crate::frontend::{Audio, Silence, Tone};
+use crate::quirks::Quirks;
+use crate::platform::Platform;
+use crate::instruction::Instruction;
+use crate::rng::{Chip8Rng, RandomSource, RngKind};
+use crate::savestate::{StateReader, StateWriter};
+use crate::error::{Chip8Error, MemoryAccess, SaveStateError, StepOutcome};
+use crate::config::{
+    CHIP8_PROGRAM_LOAD_ADDRESS, CHIP_8_TOTAL_KEYS, CHIP8_DEFAULT_SPRITE_HEIGHT,
+    CHIP_8_TOTAL_STACK_DEPTH, CHIP8_BIG_SPRITE_HEIGHT, CHIP_8_BIG_CHARACTER_SET_LOAD_ADDRESS,
+    CHIP_8_TOTAL_RPL_FLAGS, CHIP_8_AUDIO_PATTERN_SIZE, CHIP_8_DEFAULT_PITCH,
+    CHIP_8_TOTAL_DATA_REGISTER, CHIP_8_HIRES_WIDTH,
+};
+
+// A data memory access made by an instruction, recorded while tracing is enabled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryEvent {
+    pub addr: usize,
+    pub access: MemoryAccess,
+    pub value: u8,
+}
 
 pub struct Chip8 {
     pub chip8_memory: Chip8Memory,
@@ -13,161 +31,455 @@ pub struct Chip8 {
     pub chip8_stack: Chip8Stack,
     pub chip8_keyboard: Chip8Keyboard,
     pub chip8_screen: Chip8Screen,
+    pub platform: Platform,
+    pub quirks: Quirks,
+    // SUPER-CHIP RPL user flags saved and restored by FX75/FX85
+    pub rpl_flags: [u8; CHIP_8_TOTAL_RPL_FLAGS],
+    // XO-CHIP 1-bit audio pattern loaded by F002, played back at a rate set by FX3A
+    pub audio_pattern: Option<[u8; CHIP_8_AUDIO_PATTERN_SIZE]>,
+    pub pitch: u8,
+    // Source of CXNN random numbers, seeded from the OS unless replaced
+    pub rng: Box<dyn RandomSource>,
+    // Where the sound timer is played, silent unless a frontend provides a device
+    pub audio: Box<dyn Audio>,
+    // Set after a draw when the display wait quirk is on, cleared on the next frame
+    vblank_wait: bool,
+    // Accesses made by the last tick, None while tracing is off
+    memory_trace: Option<Vec<MemoryEvent>>,
 }
 
 impl Chip8 {
     pub fn new() -> Self {
+        Chip8::with_platform(Platform::Chip8)
+    }
+
+    // Create an interpreter for the given platform using its default quirks
+    pub fn with_platform(platform: Platform) -> Self {
         Chip8 {
-            chip8_memory: Chip8Memory::new(),
+            chip8_memory: Chip8Memory::with_size(platform.memory_size()),
             registers: Chip8Regsiters::new(),
             chip8_stack: Chip8Stack::new(),
             chip8_keyboard: Chip8Keyboard::new(),
-            chip8_screen: Chip8Screen::new(),     
-        }        
+            chip8_screen: Chip8Screen::new(),
+            platform,
+            quirks: platform.default_quirks(),
+            rpl_flags: [0; CHIP_8_TOTAL_RPL_FLAGS],
+            audio_pattern: None,
+            pitch: CHIP_8_DEFAULT_PITCH,
+            rng: Box::new(Chip8Rng::new()),
+            audio: Box::new(Silence),
+            vblank_wait: false,
+            memory_trace: None,
+        }
     }
 
-    fn chip8_stack_push(&mut self, val: u16) {
+    fn chip8_stack_push(&mut self, val: u16) -> Result<(), Chip8Error> {
+        if self.registers.sp as usize >= CHIP_8_TOTAL_STACK_DEPTH {
+            return Err(Chip8Error::StackOverflow { pc: self.current_pc() });
+        }
         self.chip8_stack.stack[self.registers.sp as usize] = val;
         self.registers.sp += 1;
+        Ok(())
     }
 
-    fn chip8_stack_pop(&mut self) -> u16 {
+    fn chip8_stack_pop(&mut self) -> Result<u16, Chip8Error> {
+        if self.registers.sp == 0 {
+            return Err(Chip8Error::StackUnderflow { pc: self.current_pc() });
+        }
         self.registers.sp -= 1;
-        self.chip8_stack.stack[self.registers.sp as usize]
+        Ok(self.chip8_stack.stack[self.registers.sp as usize])
+    }
+
+    // Address of the instruction currently being executed
+    fn current_pc(&self) -> u16 {
+        self.registers.pc.wrapping_sub(2)
+    }
+
+    // Record the memory accesses made by each tick, for watchpoints
+    pub fn set_memory_tracing(&mut self, enabled: bool) {
+        self.memory_trace = if enabled { Some(Vec::new()) } else { None };
+    }
+
+    pub fn is_memory_tracing(&self) -> bool {
+        self.memory_trace.is_some()
+    }
+
+    // Memory accesses made by the last tick; empty unless tracing is enabled
+    pub fn memory_events(&self) -> &[MemoryEvent] {
+        self.memory_trace.as_deref().unwrap_or(&[])
+    }
+
+    fn trace(&mut self, addr: usize, access: MemoryAccess, value: u8) {
+        if let Some(trace) = self.memory_trace.as_mut() {
+            trace.push(MemoryEvent { addr, access, value });
+        }
+    }
+
+    // Bounds checked memory accessors used by the interpreter. Every data access an
+    // instruction makes goes through these so it can be traced.
+    fn read_memory(&mut self, addr: usize) -> Result<u8, Chip8Error> {
+        let value = self.chip8_memory.memory.get(addr).copied()
+            .ok_or(Chip8Error::MemoryOutOfBounds { addr, access: MemoryAccess::Read })?;
+        self.trace(addr, MemoryAccess::Read, value);
+        Ok(value)
+    }
+
+    fn read_memory_range(&mut self, addr: usize, len: usize) -> Result<Vec<u8>, Chip8Error> {
+        let end = addr + len;
+        let size = self.chip8_memory.memory.len();
+        if end > size {
+            let bad = addr.max(size);
+            return Err(Chip8Error::MemoryOutOfBounds { addr: bad, access: MemoryAccess::Read });
+        }
+        let bytes = self.chip8_memory.memory[addr..end].to_vec();
+        for (offset, &value) in bytes.iter().enumerate() {
+            self.trace(addr + offset, MemoryAccess::Read, value);
+        }
+        Ok(bytes)
     }
 
-    pub fn get_display(&self) -> &[[bool; 64]; 32] {
-        &self.chip8_screen.pixels
+    fn write_memory(&mut self, addr: usize, val: u8) -> Result<(), Chip8Error> {
+        match self.chip8_memory.memory.get_mut(addr) {
+            Some(byte) => {
+                *byte = val;
+                self.trace(addr, MemoryAccess::Write, val);
+                Ok(())
+            },
+            None => Err(Chip8Error::MemoryOutOfBounds { addr, access: MemoryAccess::Write }),
+        }
     }
 
-    pub fn tick(&mut self) {
+    // Snapshot everything needed to resume execution later. The format is
+    // described in the savestate module.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        state.u8(match self.platform {
+            Platform::Chip8 => 0,
+            Platform::SuperChip => 1,
+            Platform::XoChip => 2,
+        });
+        let quirks = [
+            self.quirks.shift,
+            self.quirks.load_store_increment_i,
+            self.quirks.jump_vx,
+            self.quirks.vf_reset,
+            self.quirks.clip_sprites,
+            self.quirks.display_wait,
+        ];
+        for quirk in quirks {
+            state.bool(quirk);
+        }
+
+        state.u32(self.chip8_memory.memory.len() as u32);
+        state.bytes(&self.chip8_memory.memory);
+
+        state.bytes(&self.registers.v);
+        state.u16(self.registers.i);
+        state.u8(self.registers.dt);
+        state.u8(self.registers.st);
+        state.u16(self.registers.pc);
+        state.u16(self.registers.sp);
+        for &addr in self.chip8_stack.stack.iter() {
+            state.u16(addr);
+        }
+        for &down in self.chip8_keyboard.keyboard.iter() {
+            state.bool(down);
+        }
+
+        state.bool(self.chip8_screen.hires);
+        state.u8(self.chip8_screen.planes);
+        for row in self.chip8_screen.pixels.iter() {
+            state.bytes(row);
+        }
+
+        state.bytes(&self.rpl_flags);
+        match self.audio_pattern {
+            Some(pattern) => {
+                state.bool(true);
+                state.bytes(&pattern);
+            },
+            None => state.bool(false),
+        }
+        state.u8(self.pitch);
+        state.u8(match self.rng.kind() {
+            RngKind::Xorshift => 0,
+            RngKind::Vip => 1,
+        });
+        state.u64(self.rng.state());
+        state.bool(self.vblank_wait);
+        state.finish()
+    }
+
+    // Restore a snapshot taken by save_state. The interpreter is left untouched
+    // if the state can't be read.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), SaveStateError> {
+        let mut state = StateReader::new(data)?;
+        let platform = match state.u8()? {
+            0 => Platform::Chip8,
+            1 => Platform::SuperChip,
+            2 => Platform::XoChip,
+            other => return Err(SaveStateError::Invalid(format!("unknown platform {}", other))),
+        };
+        let mut chip8 = Chip8::with_platform(platform);
+        chip8.quirks = Quirks {
+            shift: state.bool()?,
+            load_store_increment_i: state.bool()?,
+            jump_vx: state.bool()?,
+            vf_reset: state.bool()?,
+            clip_sprites: state.bool()?,
+            display_wait: state.bool()?,
+        };
+
+        let memory_size = state.u32()? as usize;
+        if memory_size != platform.memory_size() {
+            return Err(SaveStateError::Invalid(format!("memory size {:#x} doesn't match the platform", memory_size)));
+        }
+        chip8.chip8_memory.memory.copy_from_slice(state.bytes(memory_size)?);
+
+        chip8.registers.v.copy_from_slice(state.bytes(CHIP_8_TOTAL_DATA_REGISTER)?);
+        chip8.registers.i = state.u16()?;
+        chip8.registers.dt = state.u8()?;
+        chip8.registers.st = state.u8()?;
+        chip8.registers.pc = state.u16()?;
+        chip8.registers.sp = state.u16()?;
+        if chip8.registers.sp as usize > CHIP_8_TOTAL_STACK_DEPTH {
+            return Err(SaveStateError::Invalid(format!("stack pointer {} is too deep", chip8.registers.sp)));
+        }
+        for addr in chip8.chip8_stack.stack.iter_mut() {
+            *addr = state.u16()?;
+        }
+        for down in chip8.chip8_keyboard.keyboard.iter_mut() {
+            *down = state.bool()?;
+        }
+
+        chip8.chip8_screen.hires = state.bool()?;
+        chip8.chip8_screen.planes = state.u8()?;
+        if chip8.chip8_screen.planes > 0b11 {
+            return Err(SaveStateError::Invalid(format!("plane mask {:#04b} selects unknown planes", chip8.chip8_screen.planes)));
+        }
+        for row in chip8.chip8_screen.pixels.iter_mut() {
+            row.copy_from_slice(state.bytes(CHIP_8_HIRES_WIDTH)?);
+        }
+
+        chip8.rpl_flags.copy_from_slice(state.bytes(CHIP_8_TOTAL_RPL_FLAGS)?);
+        if state.bool()? {
+            let mut pattern = [0; CHIP_8_AUDIO_PATTERN_SIZE];
+            pattern.copy_from_slice(state.bytes(CHIP_8_AUDIO_PATTERN_SIZE)?);
+            chip8.audio_pattern = Some(pattern);
+        }
+        chip8.pitch = state.u8()?;
+        let rng_kind = match state.u8()? {
+            0 => RngKind::Xorshift,
+            1 => RngKind::Vip,
+            other => return Err(SaveStateError::Invalid(format!("unknown random source {}", other))),
+        };
+        chip8.rng = rng_kind.source(state.u64()?);
+        chip8.vblank_wait = state.bool()?;
+        state.finish()?;
+
+        // Tracing and the audio device are settings rather than machine state,
+        // so keep them
+        chip8.memory_trace = self.memory_trace.take();
+        std::mem::swap(&mut chip8.audio, &mut self.audio);
+        *self = chip8;
+        Ok(())
+    }
+
+    pub fn get_display(&self) -> &Chip8Screen {
+        &self.chip8_screen
+    }
+
+    pub fn tick(&mut self) -> Result<StepOutcome, Chip8Error> {
+        if let Some(trace) = self.memory_trace.as_mut() {
+            trace.clear();
+        }
+        if self.vblank_wait {
+            return Ok(StepOutcome::WaitingForVBlank);
+        }
         // Fetch
-        let op = self.fetch();
+        let op = self.fetch()?;
         // Decode & execute
-        self.execute(op);
+        self.execute(op)
     }
 
     pub fn tick_timers(&mut self) {
+        self.vblank_wait = false;
+
         if self.registers.dt > 0 {
             self.registers.dt -= 1;
         }
 
+        let tone = self.tone();
+        self.audio.set_tone(tone);
         if self.registers.st > 0 {
-            beep(15000, 10).unwrap();
             self.registers.st -= 1;
         }
     }
 
+    // What the sound timer plays until the next timer tick
+    pub fn tone(&self) -> Option<Tone> {
+        if self.registers.st == 0 {
+            return None;
+        }
+        match self.audio_pattern {
+            Some(pattern) => Some(Tone::Pattern { pattern, pitch: self.pitch }),
+            None => Some(Tone::Beep),
+        }
+    }
+
     // Load a program into memory, starting at the program load address
-    pub fn chip8_load(&mut self, buffer: &[u8], size: usize) {
-        assert!(size + CHIP8_PROGRAM_LOAD_ADDRESS < CHIP_8_MEMORY_SIZE);
-        for (i, &data) in buffer.iter().enumerate() {
+    pub fn chip8_load(&mut self, buffer: &[u8], size: usize) -> Result<(), Chip8Error> {
+        let capacity = self.chip8_memory.memory.len() - CHIP8_PROGRAM_LOAD_ADDRESS;
+        if size > capacity {
+            return Err(Chip8Error::RomTooLarge { size, capacity });
+        }
+        for (i, &data) in buffer.iter().take(size).enumerate() {
             self.chip8_memory.memory[CHIP8_PROGRAM_LOAD_ADDRESS + i] = data;
         }
         self.registers.pc = CHIP8_PROGRAM_LOAD_ADDRESS as u16;
+        Ok(())
     }
 
-    fn fetch(&mut self) -> u16 {
-        let higher_byte = self.chip8_memory.memory[self.registers.pc as usize] as u16;
-        let lower_byte = self.chip8_memory.memory[(self.registers.pc + 1) as usize] as u16;
+    fn fetch(&mut self) -> Result<u16, Chip8Error> {
+        let pc = self.registers.pc as usize;
+        if pc + 1 >= self.chip8_memory.memory.len() {
+            return Err(Chip8Error::MemoryOutOfBounds { addr: pc, access: MemoryAccess::Fetch });
+        }
+        let higher_byte = self.chip8_memory.memory[pc] as u16;
+        let lower_byte = self.chip8_memory.memory[pc + 1] as u16;
         let op = (higher_byte << 8) | lower_byte;
-        self.registers.pc += 2;
-        op
+        self.registers.pc = self.registers.pc.wrapping_add(2);
+        Ok(op)
     }
 
-    fn execute(&mut self, op: u16) {
-        let digit1 = (op & 0xF000) >> 12;
-        let digit2 = (op & 0x0F00) >> 8;
-        let digit3 = (op & 0x00F0) >> 4;
-        let digit4 = op & 0x000F;
-
-        match (digit1, digit2, digit3, digit4) {
-            // NOP
-            (0, 0, 0, 0) => return,
-            // CLS
-            (0, 0, 0xE, 0) => {
+    // Skip the next instruction. On XO-CHIP the F000 NNNN long load is four bytes wide.
+    fn skip_next(&mut self) {
+        let pc = self.registers.pc as usize;
+        let memory = &self.chip8_memory.memory;
+        let next = match (memory.get(pc), memory.get(pc + 1)) {
+            (Some(&high), Some(&low)) => Instruction::decode((high as u16) << 8 | low as u16).ok(),
+            _ => None,
+        };
+        let len = match next {
+            Some(instruction) if instruction.platform() <= self.platform => instruction.size(),
+            _ => 2,
+        };
+        self.registers.pc = self.registers.pc.wrapping_add(len);
+    }
+
+    // Registers touched by 5XY2/5XY3, in order from X to Y which may count down
+    fn register_range(x: usize, y: usize) -> Box<dyn Iterator<Item = usize>> {
+        if x <= y {
+            Box::new(x..=y)
+        } else {
+            Box::new((y..=x).rev())
+        }
+    }
+
+    fn execute(&mut self, op: u16) -> Result<StepOutcome, Chip8Error> {
+        let invalid = Chip8Error::InvalidOpcode { pc: self.current_pc(), op };
+        let instruction = Instruction::decode(op).map_err(|_| invalid.clone())?;
+        // Instructions from a later dialect are not understood by this one
+        if instruction.platform() > self.platform {
+            return Err(invalid);
+        }
+
+        match instruction {
+            Instruction::Nop => (),
+            Instruction::Cls => {
                 self.chip8_screen.clear_screen();
             },
-            // RET
-            (0, 0, 0xE, 0xE) => {
-                let ret_addr = self.chip8_stack_pop();
+            Instruction::Ret => {
+                let ret_addr = self.chip8_stack_pop()?;
                 self.registers.pc = ret_addr;
             },
-            // JMP NNN
-            (1, _, _, _) => {
-                let nnn = op & 0xFFF;
+            Instruction::ScrollDown { n } => {
+                self.chip8_screen.scroll_down(n as usize);
+            },
+            Instruction::ScrollUp { n } => {
+                self.chip8_screen.scroll_up(n as usize);
+            },
+            Instruction::ScrollRight => {
+                self.chip8_screen.scroll_right(4);
+            },
+            Instruction::ScrollLeft => {
+                self.chip8_screen.scroll_left(4);
+            },
+            Instruction::Exit => {
+                // Stay on this opcode so the interpreter remains halted
+                self.registers.pc = self.registers.pc.wrapping_sub(2);
+                return Ok(StepOutcome::Exited);
+            },
+            Instruction::Lores => {
+                self.chip8_screen.set_hires(false);
+            },
+            Instruction::Hires => {
+                self.chip8_screen.set_hires(true);
+            },
+            Instruction::Jump { nnn } => {
                 self.registers.pc = nnn;
             },
-            // CALL NNN
-            (2, _, _, _) => {
-                let nnn = op & 0xFFF;
-                self.chip8_stack_push(self.registers.pc);
+            Instruction::Call { nnn } => {
+                self.chip8_stack_push(self.registers.pc)?;
                 self.registers.pc = nnn;
             },
-            // SKIP VX == NN
-            (3, _, _, _) => {
-                let x = digit2 as usize;
-                let nn = (op & 0xFF) as u8;
-                if self.registers.v[x] == nn {
-                    self.registers.pc += 2;
+            Instruction::SkipEqImm { x, nn } => {
+                if self.registers.v[x as usize] == nn {
+                    self.skip_next();
                 }
             },
-            // SKIP VX != NN
-            (4, _, _, _) => {
-                let x = digit2 as usize;
-                let nn = (op & 0xFF) as u8;
-                if self.registers.v[x] != nn {
-                    self.registers.pc += 2;
+            Instruction::SkipNeImm { x, nn } => {
+                if self.registers.v[x as usize] != nn {
+                    self.skip_next();
                 }
             },
-            // SKIP VX == VY
-            (5, _, _, _) => {
-                let x = digit2 as usize;
-                let y = digit3 as usize;
-                if self.registers.v[x] == self.registers.v[y] {
-                    self.registers.pc += 2;
+            Instruction::SkipEq { x, y } => {
+                if self.registers.v[x as usize] == self.registers.v[y as usize] {
+                    self.skip_next();
+                }
+            },
+            Instruction::StoreRange { x, y } => {
+                let i = self.registers.i as usize;
+                for (offset, idx) in Self::register_range(x as usize, y as usize).enumerate() {
+                    self.write_memory(i + offset, self.registers.v[idx])?;
+                }
+            },
+            Instruction::LoadRange { x, y } => {
+                let i = self.registers.i as usize;
+                for (offset, idx) in Self::register_range(x as usize, y as usize).enumerate() {
+                    self.registers.v[idx] = self.read_memory(i + offset)?;
                 }
             },
-            // VX = NN
-            (6, _, _, _) => {
-                let x = digit2 as usize;
-                let nn = (op & 0xFF) as u8;
-                self.registers.v[x] = nn;
+            Instruction::SetImm { x, nn } => {
+                self.registers.v[x as usize] = nn;
             },
-            // VX += NN
-            (7, _, _, _) => {
-                let x = digit2 as usize;
-                let nn = (op & 0xFF) as u8;
+            Instruction::AddImm { x, nn } => {
+                let x = x as usize;
                 self.registers.v[x] = self.registers.v[x].wrapping_add(nn);
             },
-            // VX = VY
-            (8, _, _, 0) => {
-                let x = digit2 as usize;
-                let y = digit3 as usize;
-                self.registers.v[x] = self.registers.v[y];
-            },
-            // VX |= VY
-            (8, _, _, 1) => {
-                let x = digit2 as usize;
-                let y = digit3 as usize;
-                self.registers.v[x] |= self.registers.v[y];
-            },
-            // VX &= VY
-            (8, _, _, 2) => {
-                let x = digit2 as usize;
-                let y = digit3 as usize;
-                self.registers.v[x] &= self.registers.v[y];
-            },
-            // VX ^= VY
-            (8, _, _, 3) => {
-                let x = digit2 as usize;
-                let y = digit3 as usize;
-                self.registers.v[x] ^= self.registers.v[y];
-            },
-            // VX += VY
-            (8, _, _, 4) => {
-                let x = digit2 as usize;
-                let y = digit3 as usize;
+            Instruction::Set { x, y } => {
+                self.registers.v[x as usize] = self.registers.v[y as usize];
+            },
+            Instruction::Or { x, y } => {
+                self.registers.v[x as usize] |= self.registers.v[y as usize];
+                if self.quirks.vf_reset {
+                    self.registers.v[0xF] = 0;
+                }
+            },
+            Instruction::And { x, y } => {
+                self.registers.v[x as usize] &= self.registers.v[y as usize];
+                if self.quirks.vf_reset {
+                    self.registers.v[0xF] = 0;
+                }
+            },
+            Instruction::Xor { x, y } => {
+                self.registers.v[x as usize] ^= self.registers.v[y as usize];
+                if self.quirks.vf_reset {
+                    self.registers.v[0xF] = 0;
+                }
+            },
+            Instruction::Add { x, y } => {
+                let (x, y) = (x as usize, y as usize);
 
                 let (new_vx, carry) = self.registers.v[x].overflowing_add(self.registers.v[y]);
                 let new_vf = if carry { 1 } else { 0 };
@@ -175,10 +487,8 @@ impl Chip8 {
                 self.registers.v[x] = new_vx;
                 self.registers.v[0xF] = new_vf;
             },
-            // VX -= VY
-            (8, _, _, 5) => {
-                let x = digit2 as usize;
-                let y = digit3 as usize;
+            Instruction::Sub { x, y } => {
+                let (x, y) = (x as usize, y as usize);
 
                 let (new_vx, borrow) = self.registers.v[x].overflowing_sub(self.registers.v[y]);
                 let new_vf = if borrow { 0 } else { 1 };
@@ -186,17 +496,15 @@ impl Chip8 {
                 self.registers.v[x] = new_vx;
                 self.registers.v[0xF] = new_vf;
             },
-            // VX >>= 1
-            (8, _, _, 6) => {
-                let x = digit2 as usize;
-                let lsb = self.registers.v[x] & 1;
-                self.registers.v[x] >>= 1;
+            Instruction::Shr { x, y } => {
+                let x = x as usize;
+                let y = if self.quirks.shift { x } else { y as usize };
+                let lsb = self.registers.v[y] & 1;
+                self.registers.v[x] = self.registers.v[y] >> 1;
                 self.registers.v[0xF] = lsb;
             },
-            // VX = VY - VX
-            (8, _, _, 7) => {
-                let x = digit2 as usize;
-                let y = digit3 as usize;
+            Instruction::SubN { x, y } => {
+                let (x, y) = (x as usize, y as usize);
 
                 let (new_vx, borrow) = self.registers.v[y].overflowing_sub(self.registers.v[x]);
                 let new_vf = if borrow { 0 } else { 1 };
@@ -204,116 +512,113 @@ impl Chip8 {
                 self.registers.v[x] = new_vx;
                 self.registers.v[0xF] = new_vf;
             },
-            // VX <<= 1
-            (8, _, _, 0xE) => {
-                let x = digit2 as usize;
-                let msb = (self.registers.v[x] >> 7) & 1;
-                self.registers.v[x] <<= 1;
+            Instruction::Shl { x, y } => {
+                let x = x as usize;
+                let y = if self.quirks.shift { x } else { y as usize };
+                let msb = (self.registers.v[y] >> 7) & 1;
+                self.registers.v[x] = self.registers.v[y] << 1;
                 self.registers.v[0xF] = msb;
             },
-            // SKIP VX != VY
-            (9, _, _, 0) => {
-                let x = digit2 as usize;
-                let y = digit3 as usize;
-                if self.registers.v[x] != self.registers.v[y] {
-                    self.registers.pc += 2;
+            Instruction::SkipNe { x, y } => {
+                if self.registers.v[x as usize] != self.registers.v[y as usize] {
+                    self.skip_next();
                 }
             },
-            // I = NNN
-            (0xA, _, _, _) => {
-                let nnn = op & 0xFFF;
+            Instruction::SetI { nnn } => {
                 self.registers.i = nnn;
             },
-            // JMP V0 + NNN
-            (0xB, _, _, _) => {
-                let nnn = op & 0xFFF;
-                self.registers.pc = (self.registers.v[0] as u16) + nnn;
+            Instruction::JumpOffset { nnn } => {
+                let offset = if self.quirks.jump_vx { (nnn >> 8) as usize } else { 0 };
+                self.registers.pc = (self.registers.v[offset] as u16) + nnn;
             },
-            // VX = rand() & NN
-            (0xC, _, _, _) => {
-                let x = digit2 as usize;
-                let nn = (op & 0xFF) as u8;
-                let rng: u8 = rand::thread_rng().gen();
-                self.registers.v[x] = rng & nn;
+            Instruction::Random { x, nn } => {
+                self.registers.v[x as usize] = self.rng.next_byte(&self.chip8_memory.memory) & nn;
             },
-            // DRAW
-            (0xD, _, _, _) => {
+            Instruction::Draw { x, y, n } => {
                 // Get the (x, y) coords for our sprite
-                let x = self.registers.v[digit2 as usize] as usize;
-                let y = self.registers.v[digit3 as usize] as usize;
-                // The last digit determines how many rows high our sprite is
-                let num = digit4;
+                let x = self.registers.v[x as usize] as usize;
+                let y = self.registers.v[y as usize] as usize;
+                // DXY0 draws a 16x16 sprite from SUPER-CHIP onwards
+                let large = n == 0 && self.platform >= Platform::SuperChip;
+                let rows = if large { 32 } else { n as usize };
 
                 let start = self.registers.i as usize;
-                let end = start + num as usize;
-                let sprite_iter = &self.chip8_memory.memory[start..end];
-                self.registers.v[0x0f] = self.chip8_screen.chip8_screen_draw_sprite(x , y, sprite_iter);
-            },
-            // SKIP KEY PRESS
-            (0xE, _, 9, 0xE) => {
-                let x = digit2 as usize;
-                let vx = self.registers.v[x];
-                let key = self.chip8_keyboard.keyboard[vx as usize];
-                if key {
-                    self.registers.pc += 2;
+                let len = rows * self.chip8_screen.selected_plane_count();
+                let sprite_iter = self.read_memory_range(start, len)?;
+                let clip = self.quirks.clip_sprites;
+                self.registers.v[0x0f] = if large {
+                    self.chip8_screen.chip8_screen_draw_large_sprite(x , y, &sprite_iter, clip)
+                } else {
+                    self.chip8_screen.chip8_screen_draw_sprite(x , y, &sprite_iter, clip)
+                };
+                if self.quirks.display_wait {
+                    self.vblank_wait = true;
                 }
             },
-            // SKIP KEY RELEASE
-            (0xE, _, 0xA, 1) => {
-                let x = digit2 as usize;
-                let vx = self.registers.v[x];
-                let key = self.chip8_keyboard.keyboard[vx as usize];
-                if !key {
-                    self.registers.pc += 2;
+            Instruction::SkipKey { x } => {
+                let vx = self.registers.v[x as usize];
+                if self.chip8_keyboard.keyboard[(vx & 0xF) as usize] {
+                    self.skip_next();
                 }
             },
-            // VX = DT
-            (0xF, _, 0, 7) => {
-                let x = digit2 as usize;
-                self.registers.v[x] = self.registers.dt;
+            Instruction::SkipNotKey { x } => {
+                let vx = self.registers.v[x as usize];
+                if !self.chip8_keyboard.keyboard[(vx & 0xF) as usize] {
+                    self.skip_next();
+                }
+            },
+            Instruction::LongSetI => {
+                // The NNNN operand is part of the instruction, so it is fetched rather
+                // than read and doesn't trigger read watchpoints
+                self.registers.i = self.fetch()?;
             },
-            // WAIT KEY
-            (0xF, _, 0, 0xA) => {
-                let x = digit2 as usize;
+            Instruction::SelectPlanes { n } => {
+                self.chip8_screen.select_planes(n);
+            },
+            Instruction::LoadAudio => {
+                let i = self.registers.i as usize;
+                let mut pattern = [0u8; CHIP_8_AUDIO_PATTERN_SIZE];
+                pattern.copy_from_slice(&self.read_memory_range(i, CHIP_8_AUDIO_PATTERN_SIZE)?);
+                self.audio_pattern = Some(pattern);
+            },
+            Instruction::GetDelay { x } => {
+                self.registers.v[x as usize] = self.registers.dt;
+            },
+            Instruction::WaitKey { x } => {
                 let mut pressed = false;
                 for i in 0..CHIP_8_TOTAL_KEYS {
                     if self.chip8_keyboard.keyboard[i] {
-                        self.registers.v[x] = i as u8;
+                        self.registers.v[x as usize] = i as u8;
                         pressed = true;
                         break;
                     }
                 }
                 if !pressed {
                     // Redo opcode
-                    self.registers.pc -= 2;
+                    self.registers.pc = self.registers.pc.wrapping_sub(2);
+                    return Ok(StepOutcome::WaitingForKey);
                 }
             },
-            // DT = VX
-            (0xF, _, 1, 5) => {
-                let x = digit2 as usize;
-                self.registers.dt = self.registers.v[x];
+            Instruction::SetDelay { x } => {
+                self.registers.dt = self.registers.v[x as usize];
             },
-            // ST = VX
-            (0xF, _, 1, 8) => {
-                let x = digit2 as usize;
-                self.registers.st = self.registers.v[x];
+            Instruction::SetSound { x } => {
+                self.registers.st = self.registers.v[x as usize];
             },
-            // I += VX
-            (0xF, _, 1, 0xE) => {
-                let x = digit2 as usize;
-                let vx = self.registers.v[x] as u16;
+            Instruction::AddI { x } => {
+                let vx = self.registers.v[x as usize] as u16;
                 self.registers.i = self.registers.i.wrapping_add(vx);
             },
-            // I = FONT
-            (0xF, _, 2, 9) => {
-                let x = digit2 as usize;
-                let c = self.registers.v[x] as u16;
+            Instruction::Font { x } => {
+                let c = self.registers.v[x as usize] as u16;
                 self.registers.i = c * CHIP8_DEFAULT_SPRITE_HEIGHT;
             },
-            // BCD
-            (0xF, _, 3, 3) => {
-                let x = digit2 as usize;
-                let vx = self.registers.v[x] as f32;
+            Instruction::BigFont { x } => {
+                let c = (self.registers.v[x as usize] & 0xF) as u16;
+                self.registers.i = CHIP_8_BIG_CHARACTER_SET_LOAD_ADDRESS as u16 + c * CHIP8_BIG_SPRITE_HEIGHT;
+            },
+            Instruction::Bcd { x } => {
+                let vx = self.registers.v[x as usize] as f32;
 
                 // Fetch the hundreds digit by dividing by 100 and tossing the decimal
                 let hundreds = (vx / 100.0).floor() as u8;
@@ -322,30 +627,84 @@ impl Chip8 {
                 // Fetch the ones digit by tossing the hundreds and the tens
                 let ones = (vx % 10.0) as u8;
 
-                self.chip8_memory.memory[self.registers.i as usize] = hundreds;
-                self.chip8_memory.memory[(self.registers.i + 1) as usize] = tens;
-                self.chip8_memory.memory[(self.registers.i + 2) as usize] = ones;
+                let i = self.registers.i as usize;
+                self.write_memory(i, hundreds)?;
+                self.write_memory(i + 1, tens)?;
+                self.write_memory(i + 2, ones)?;
+            },
+            Instruction::Pitch { x } => {
+                self.pitch = self.registers.v[x as usize];
             },
-            // STORE V0 - VX
-            (0xF, _, 5, 5) => {
-                let x = digit2 as usize;
+            Instruction::Store { x } => {
+                let x = x as usize;
                 let i = self.registers.i as usize;
                 for idx in 0..=x {
-                    self.chip8_memory.memory[i + idx] = self.registers.v[idx];
+                    self.write_memory(i + idx, self.registers.v[idx])?;
+                }
+                if self.quirks.load_store_increment_i {
+                    self.registers.i = self.registers.i.wrapping_add(x as u16 + 1);
                 }
             },
-            // LOAD V0 - VX
-            (0xF, _, 6, 5) => {
-                let x = digit2 as usize;
+            Instruction::Load { x } => {
+                let x = x as usize;
                 let i = self.registers.i as usize;
                 for idx in 0..=x {
-                    self.registers.v[idx] = self.chip8_memory.memory[i + idx];
+                    self.registers.v[idx] = self.read_memory(i + idx)?;
+                }
+                if self.quirks.load_store_increment_i {
+                    self.registers.i = self.registers.i.wrapping_add(x as u16 + 1);
                 }
             },
-            (_, _, _, _) => unimplemented!("Unimplemented opcode: {:#04x}", op),
+            Instruction::StoreFlags { x } => {
+                let x = x as usize;
+                self.rpl_flags[..=x].copy_from_slice(&self.registers.v[..=x]);
+            },
+            Instruction::LoadFlags { x } => {
+                let x = x as usize;
+                self.registers.v[..=x].copy_from_slice(&self.rpl_flags[..=x]);
+            },
         }
+        Ok(StepOutcome::Executed)
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
 
+    // An XO-CHIP machine about to run `op` from the very end of memory, where
+    // fetching it wraps the PC round to 0
+    fn at_end_of_memory(op: u16) -> Chip8 {
+        let mut chip8 = Chip8::with_platform(Platform::XoChip);
+        let end = chip8.chip8_memory.memory.len() - 2;
+        chip8.chip8_memory.memory[end] = (op >> 8) as u8;
+        chip8.chip8_memory.memory[end + 1] = op as u8;
+        chip8.registers.pc = end as u16;
+        chip8
+    }
 
+    #[test]
+    fn wait_key_at_end_of_memory_stays_put() {
+        let mut chip8 = at_end_of_memory(0xF00A);
+        assert_eq!(chip8.tick().unwrap(), StepOutcome::WaitingForKey);
+        assert_eq!(chip8.registers.pc, 0xFFFE);
+    }
+
+    #[test]
+    fn long_set_i_operand_is_not_a_data_read() {
+        let mut chip8 = Chip8::with_platform(Platform::XoChip);
+        chip8.chip8_load(&[0xF0, 0x00, 0x12, 0x34], 4).unwrap();
+        chip8.set_memory_tracing(true);
+        chip8.tick().unwrap();
+        assert_eq!(chip8.registers.i, 0x1234);
+        assert_eq!(chip8.registers.pc, 0x204);
+        assert!(chip8.memory_events().is_empty());
+    }
+
+    #[test]
+    fn exit_at_end_of_memory_stays_put() {
+        let mut chip8 = at_end_of_memory(0x00FD);
+        assert_eq!(chip8.tick().unwrap(), StepOutcome::Exited);
+        assert_eq!(chip8.registers.pc, 0xFFFE);
+    }
+}
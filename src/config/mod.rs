@@ -1,53 +1,38 @@
-use sdl2::keyboard::Keycode;
-
 pub const CHIP_8_MEMORY_SIZE: usize = 4096;
+pub const CHIP_8_XO_MEMORY_SIZE: usize = 65536;
 pub const CHIP_8_HEIGHT: usize = 32;
 pub const CHIP_8_WIDTH: usize = 64;
+pub const CHIP_8_HIRES_HEIGHT: usize = 64;
+pub const CHIP_8_HIRES_WIDTH: usize = 128;
 pub const EMULATOR_WINDOW_TITLE: &str = "Chip-8 Emulator";
-pub const CHIP_8_WINDOW_MULTIPLIER: usize = 15;
+pub const CHIP_8_DEFAULT_WINDOW_SCALE: usize = 15;
 pub const CHIP8_PROGRAM_LOAD_ADDRESS: usize = 0x200;
 pub const CHIP8_DEFAULT_SPRITE_HEIGHT: u16 = 5;
+pub const CHIP8_BIG_SPRITE_HEIGHT: u16 = 10;
+pub const CHIP_8_TOTAL_RPL_FLAGS: usize = 16;
+pub const CHIP_8_TOTAL_PLANES: usize = 2;
+pub const CHIP_8_AUDIO_PATTERN_SIZE: usize = 16;
+pub const CHIP_8_DEFAULT_PITCH: u8 = 64;
+pub const CHIP_8_DEFAULT_TONE_FREQUENCY: f32 = 440.0;
+pub const CHIP_8_DEFAULT_VOLUME: f32 = 0.25;
+pub const CHIP_8_AUDIO_SAMPLE_RATE: u32 = 44100;
 pub const CHIP_8_TOTAL_DATA_REGISTER: usize = 16;
 pub const CHIP_8_TOTAL_STACK_DEPTH: usize = 16;
 pub const CHIP8_TICKS_PER_FRAME: usize = 10;
+pub const CHIP_8_TIMER_HZ: u32 = 60;
+pub const CHIP8_DEFAULT_INSTRUCTIONS_PER_SECOND: u32 = CHIP8_TICKS_PER_FRAME as u32 * CHIP_8_TIMER_HZ;
+pub const CHIP8_REWIND_HISTORY_FRAMES: usize = 600;
+pub const CHIP_8_DEFAULT_PHOSPHOR_DECAY: f32 = 0.4;
 
 pub const CHIP_8_TOTAL_KEYS: usize = 16;
 
-/*
-    Keyboard                    Chip-8
-    +---+---+---+---+           +---+---+---+---+
-    | 1 | 2 | 3 | 4 |           | 1 | 2 | 3 | C |
-    +---+---+---+---+           +---+---+---+---+
-    | Q | W | E | R |           | 4 | 5 | 6 | D |
-    +---+---+---+---+     =>    +---+---+---+---+
-    | A | S | D | F |           | 7 | 8 | 9 | E |
-    +---+---+---+---+           +---+---+---+---+
-    | Z | X | C | V |           | A | 0 | B | F |
-    +---+---+---+---+           +---+---+---+---+
-*/
-
-pub fn key2btn(key: Keycode) -> Option<usize> {
-    match key {
-        Keycode::Num1 =>    Some(0x1),
-        Keycode::Num2 =>    Some(0x2),
-        Keycode::Num3 =>    Some(0x3),
-        Keycode::Num4 =>    Some(0xC),
-        Keycode::Q =>       Some(0x4),
-        Keycode::W =>       Some(0x5),
-        Keycode::E =>       Some(0x6),
-        Keycode::R =>       Some(0xD),
-        Keycode::A =>       Some(0x7),
-        Keycode::S =>       Some(0x8),
-        Keycode::D =>       Some(0x9),
-        Keycode::F =>       Some(0xE),
-        Keycode::Z =>       Some(0xA),
-        Keycode::X =>       Some(0x0),
-        Keycode::C =>       Some(0xB),
-        Keycode::V =>       Some(0xF),
-        _ =>                None,
-    }
-}
-
+// RGB colours for each XO-CHIP plane combination: background, plane 1, plane 2, both planes
+pub const CHIP_8_DEFAULT_PALETTE: [[u8; 3]; 4] = [
+    [0, 0, 0],
+    [255, 255, 255],
+    [170, 170, 170],
+    [85, 85, 85],
+];
 
 pub const CHIP_8_DEFAULT_CHARACTER_SET_LOAD_ADDRESS: usize = 0x00;
 
@@ -70,3 +55,24 @@ pub const CHIP_8_DEFAULT_CHARACTER_SET: [u8; 16 * 5 ]  = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
 
+pub const CHIP_8_BIG_CHARACTER_SET_LOAD_ADDRESS: usize = 0x50;
+
+// SUPER-CHIP 8x10 font, extended with A-F as in XO-CHIP
+pub const CHIP_8_BIG_CHARACTER_SET: [u8; 16 * 10] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
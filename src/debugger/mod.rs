@@ -0,0 +1,548 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Write};
+use crate::chip8::Chip8;
+use crate::error::{Chip8Error, MemoryAccess, StepOutcome};
+use crate::instruction::Instruction;
+
+// What the debugger does with the next batch of ticks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugState {
+    Running,
+    Paused,
+    // Execute `remaining` more instructions, spread over frames like normal running
+    Step { remaining: usize },
+    // Run until the subroutine called at the current PC returns to `return_pc`
+    StepOver { sp: u16, return_pc: u16 },
+    // Run until the current subroutine returns to its caller
+    StepOut { sp: u16 },
+}
+
+// Which memory accesses trigger a watchpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+// Registers that can be watched
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Register {
+    V(u8),
+    I,
+    Dt,
+    St,
+    Sp,
+}
+
+impl WatchKind {
+    fn matches(self, access: MemoryAccess) -> bool {
+        match self {
+            WatchKind::Read => access == MemoryAccess::Read,
+            WatchKind::Write => access == MemoryAccess::Write,
+            WatchKind::ReadWrite => access != MemoryAccess::Fetch,
+        }
+    }
+
+    fn parse(text: Option<&&str>) -> Result<WatchKind, String> {
+        match text.copied() {
+            None | Some("rw") => Ok(WatchKind::ReadWrite),
+            Some("r") => Ok(WatchKind::Read),
+            Some("w") => Ok(WatchKind::Write),
+            Some(other) => Err(format!("invalid access kind '{}', use r, w or rw", other)),
+        }
+    }
+}
+
+impl Register {
+    fn parse(text: &str) -> Option<Register> {
+        match text.to_ascii_lowercase().as_str() {
+            "i" => Some(Register::I),
+            "dt" => Some(Register::Dt),
+            "st" => Some(Register::St),
+            "sp" => Some(Register::Sp),
+            name => {
+                let digit = name.strip_prefix('v')?;
+                if digit.len() != 1 {
+                    return None;
+                }
+                u8::from_str_radix(digit, 16).ok().map(Register::V)
+            },
+        }
+    }
+
+    pub fn read(self, chip8: &Chip8) -> u16 {
+        let registers = &chip8.registers;
+        match self {
+            Register::V(x) => registers.v[x as usize] as u16,
+            Register::I => registers.i,
+            Register::Dt => registers.dt as u16,
+            Register::St => registers.st as u16,
+            Register::Sp => registers.sp,
+        }
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Register::V(x) => write!(f, "V{:X}", x),
+            Register::I => write!(f, "I"),
+            Register::Dt => write!(f, "DT"),
+            Register::St => write!(f, "ST"),
+            Register::Sp => write!(f, "SP"),
+        }
+    }
+}
+
+// Breakpoints and stepping on top of Chip8::tick. Commands are plain text lines so
+// they can come from a REPL on stdin or any other frontend.
+pub struct Debugger {
+    pub breakpoints: BTreeSet<u16>,
+    pub memory_watches: BTreeMap<u16, WatchKind>,
+    // A register watch with a value triggers when the register becomes that
+    // value, without one it triggers on every change
+    pub register_watches: BTreeMap<Register, Option<u16>>,
+    pub state: DebugState,
+    // Lets execution resume from a breakpoint without stopping on it again
+    resume_from: Option<u16>,
+}
+
+const HELP: &str = "\
+commands:
+  break <addr>        set a breakpoint (b)
+  delete <addr>       remove a breakpoint (d)
+  breakpoints         list breakpoints
+  watch <addr> [r|w|rw]
+                      break when memory is read and/or written (w)
+  watch <reg> [value] break when a register changes or becomes value
+  unwatch <addr|reg>  remove a watchpoint
+  watches             list watchpoints
+  continue            resume execution (c)
+  pause               stop execution (p)
+  step [n]            execute n instructions, default 1, stopping at breakpoints (s)
+  next                step over CALL (n)
+  finish              run until the current subroutine returns (f)
+  regs                print registers (r)
+  stack               print the call stack
+  mem <addr> [len]    dump memory (x)
+  help                show this text (h)
+addresses, counts, lengths and values are hexadecimal, with or without 0x
+";
+
+impl Debugger {
+    pub fn new() -> Self {
+        Debugger {
+            breakpoints: BTreeSet::new(),
+            memory_watches: BTreeMap::new(),
+            register_watches: BTreeMap::new(),
+            state: DebugState::Running,
+            resume_from: None,
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state == DebugState::Paused
+    }
+
+    pub fn pause(&mut self) {
+        self.state = DebugState::Paused;
+    }
+
+    // Carry on running, the same as the continue command
+    pub fn unpause(&mut self, chip8: &Chip8) {
+        self.resume(chip8, DebugState::Running);
+    }
+
+    // Execute up to `ticks` instructions unless paused. Returns a message when
+    // execution stopped because of a breakpoint or a finished step.
+    pub fn run(&mut self, chip8: &mut Chip8, ticks: usize) -> Result<Option<String>, Chip8Error> {
+        let watch_memory = !self.memory_watches.is_empty();
+        if chip8.is_memory_tracing() != watch_memory {
+            chip8.set_memory_tracing(watch_memory);
+        }
+
+        for _ in 0..ticks {
+            if self.state == DebugState::Paused {
+                return Ok(None);
+            }
+            let pc = chip8.registers.pc;
+            if self.breakpoints.contains(&pc) && self.resume_from != Some(pc) {
+                self.state = DebugState::Paused;
+                return Ok(Some(format!("breakpoint at {:03X}\n{}", pc, Self::describe(chip8, pc))));
+            }
+            self.resume_from = None;
+
+            let before: Vec<(Register, u16)> = self.register_watches.keys()
+                .map(|&register| (register, register.read(chip8)))
+                .collect();
+
+            match chip8.tick()? {
+                StepOutcome::WaitingForVBlank => break,
+                // Nothing more will run, so a step in progress ends here
+                StepOutcome::Exited => {
+                    if let DebugState::Step { .. } = self.state {
+                        self.state = DebugState::Paused;
+                        return Ok(Some(Self::describe(chip8, chip8.registers.pc)));
+                    }
+                    break;
+                },
+                StepOutcome::Executed | StepOutcome::WaitingForKey => (),
+            }
+
+            if let Some(hit) = self.check_watches(chip8, &before) {
+                self.state = DebugState::Paused;
+                return Ok(Some(format!("{}\n  by {}", hit, Self::describe(chip8, pc))));
+            }
+
+            if let DebugState::Step { remaining } = &mut self.state {
+                *remaining -= 1;
+            }
+            let done = match self.state {
+                DebugState::Step { remaining } => remaining == 0,
+                DebugState::StepOver { sp, return_pc } => chip8.registers.sp == sp && chip8.registers.pc == return_pc,
+                DebugState::StepOut { sp } => chip8.registers.sp < sp,
+                _ => false,
+            };
+            if done {
+                self.state = DebugState::Paused;
+                let pc = chip8.registers.pc;
+                return Ok(Some(Self::describe(chip8, pc)));
+            }
+        }
+        Ok(None)
+    }
+
+    // Describe the first watchpoint triggered by the last tick, if any
+    fn check_watches(&self, chip8: &Chip8, before: &[(Register, u16)]) -> Option<String> {
+        for event in chip8.memory_events() {
+            let hit = self.memory_watches.get(&(event.addr as u16))
+                .is_some_and(|kind| kind.matches(event.access));
+            if hit {
+                return Some(format!("watchpoint: {} {:02X} at {:03X}", event.access, event.value, event.addr));
+            }
+        }
+        for &(register, old) in before {
+            let new = register.read(chip8);
+            if new == old {
+                continue;
+            }
+            match self.register_watches.get(&register) {
+                Some(Some(value)) if *value != new => (),
+                _ => return Some(format!("watchpoint: {} changed {:X} -> {:X}", register, old, new)),
+            }
+        }
+        None
+    }
+
+    fn watch(&mut self, args: &[&str]) -> Result<String, String> {
+        let target = args.first().ok_or("missing address or register")?;
+        if let Some(register) = Register::parse(target) {
+            let value = match args.get(1) {
+                Some(text) => Some(Self::parse_number(text).filter(|&v| v <= 0xFFFF)
+                    .ok_or(format!("invalid value '{}'", text))? as u16),
+                None => None,
+            };
+            self.register_watches.insert(register, value);
+            return Ok(format!("watching {}\n", register));
+        }
+        let addr = Self::parse_addr(args.first())?;
+        let kind = WatchKind::parse(args.get(1))?;
+        self.memory_watches.insert(addr, kind);
+        Ok(format!("watching {:03X}\n", addr))
+    }
+
+    fn unwatch(&mut self, args: &[&str]) -> Result<String, String> {
+        let target = args.first().ok_or("missing address or register")?;
+        let removed = match Register::parse(target) {
+            Some(register) => self.register_watches.remove(&register).is_some(),
+            None => self.memory_watches.remove(&Self::parse_addr(args.first())?).is_some(),
+        };
+        if removed {
+            Ok("watchpoint removed\n".to_string())
+        } else {
+            Err(format!("no watchpoint on {}", target))
+        }
+    }
+
+    fn watches(&self) -> String {
+        let mut out = String::new();
+        for (addr, kind) in &self.memory_watches {
+            let _ = writeln!(out, "{:03X}  {:?}", addr, kind);
+        }
+        for (register, value) in &self.register_watches {
+            match value {
+                Some(value) => { let _ = writeln!(out, "{} == {:X}", register, value); },
+                None => { let _ = writeln!(out, "{} changes", register); },
+            }
+        }
+        out
+    }
+
+    // Run a debugger command and return its output
+    pub fn command(&mut self, chip8: &mut Chip8, line: &str) -> String {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (command, args) = match words.split_first() {
+            Some((command, args)) => (*command, args),
+            None => return String::new(),
+        };
+        let result = match command {
+            "break" | "b" => Self::parse_addr(args.first()).map(|addr| {
+                self.breakpoints.insert(addr);
+                format!("breakpoint set at {:03X}\n", addr)
+            }),
+            "delete" | "d" => Self::parse_addr(args.first()).map(|addr| {
+                if self.breakpoints.remove(&addr) {
+                    format!("breakpoint at {:03X} deleted\n", addr)
+                } else {
+                    format!("no breakpoint at {:03X}\n", addr)
+                }
+            }),
+            "breakpoints" => Ok(self.breakpoints.iter().map(|addr| format!("{:03X}\n", addr)).collect()),
+            "watch" | "w" => self.watch(args),
+            "unwatch" => self.unwatch(args),
+            "watches" => Ok(self.watches()),
+            "continue" | "c" => {
+                self.resume(chip8, DebugState::Running);
+                Ok(String::new())
+            },
+            "pause" | "p" => {
+                self.pause();
+                Ok(Self::describe(chip8, chip8.registers.pc))
+            },
+            "step" | "s" => {
+                match args.first() {
+                    Some(n) => match Self::parse_number(n) {
+                        Some(count) => self.step(chip8, count),
+                        None => Err(format!("invalid count '{}'", n)),
+                    },
+                    None => self.step(chip8, 1),
+                }
+            },
+            "next" | "n" => {
+                let pc = chip8.registers.pc;
+                match Self::instruction_at(chip8, pc) {
+                    Some(Instruction::Call { .. }) => {
+                        let state = DebugState::StepOver { sp: chip8.registers.sp, return_pc: pc.wrapping_add(2) };
+                        self.resume(chip8, state);
+                        Ok(String::new())
+                    },
+                    _ => self.step(chip8, 1),
+                }
+            },
+            "finish" | "f" => {
+                if chip8.registers.sp == 0 {
+                    Err("not inside a subroutine".to_string())
+                } else {
+                    self.resume(chip8, DebugState::StepOut { sp: chip8.registers.sp });
+                    Ok(String::new())
+                }
+            },
+            "regs" | "r" => Ok(Self::registers(chip8)),
+            "stack" => Ok(Self::stack(chip8)),
+            "mem" | "x" => Self::parse_addr(args.first()).map(|addr| {
+                let len = args.get(1).and_then(|len| Self::parse_number(len)).unwrap_or(64);
+                Self::memory(chip8, addr as usize, len)
+            }),
+            "help" | "h" => Ok(HELP.to_string()),
+            _ => Err(format!("unknown command '{}', try 'help'", command)),
+        };
+        result.unwrap_or_else(|err| format!("error: {}\n", err))
+    }
+
+    fn resume(&mut self, chip8: &Chip8, state: DebugState) {
+        self.state = state;
+        self.resume_from = Some(chip8.registers.pc);
+    }
+
+    // Steps are carried out by run, a frame's worth of instructions at a time, so
+    // a large count doesn't hold up the frontend. Timers tick and display waits
+    // end between frames as they do while running.
+    fn step(&mut self, chip8: &Chip8, count: usize) -> Result<String, String> {
+        if count == 0 {
+            self.state = DebugState::Paused;
+            return Ok(Self::describe(chip8, chip8.registers.pc));
+        }
+        self.resume(chip8, DebugState::Step { remaining: count });
+        Ok(String::new())
+    }
+
+    fn parse_number(text: &str) -> Option<usize> {
+        match text.strip_prefix("0x") {
+            Some(hex) => usize::from_str_radix(hex, 16).ok(),
+            None => usize::from_str_radix(text, 16).ok(),
+        }
+    }
+
+    // Addresses are hexadecimal like every other number, with or without a 0x prefix
+    fn parse_addr(text: Option<&&str>) -> Result<u16, String> {
+        let text = text.ok_or("missing address")?;
+        Self::parse_number(text)
+            .filter(|&addr| addr <= 0xFFFF)
+            .map(|addr| addr as u16)
+            .ok_or(format!("invalid address '{}'", text))
+    }
+
+    fn word_at(chip8: &Chip8, addr: u16) -> Option<u16> {
+        let memory = &chip8.chip8_memory.memory;
+        let high = *memory.get(addr as usize)?;
+        let low = *memory.get(addr as usize + 1)?;
+        Some((high as u16) << 8 | low as u16)
+    }
+
+    fn instruction_at(chip8: &Chip8, pc: u16) -> Option<Instruction> {
+        Instruction::decode(Self::word_at(chip8, pc)?).ok()
+    }
+
+    // The instruction about to be executed at `pc`
+    pub fn describe(chip8: &Chip8, pc: u16) -> String {
+        match Self::instruction_at(chip8, pc) {
+            // The address is the word after F000, like the disassembler shows it
+            Some(Instruction::LongSetI) => match Self::word_at(chip8, pc.wrapping_add(2)) {
+                Some(nnnn) => format!("{:03X}  F000 {:04X}  LD I, {:#06x}\n", pc, nnnn, nnnn),
+                None => format!("{:03X}  F000  LD I, ????\n", pc),
+            },
+            Some(instruction) => format!("{:03X}  {:04X}  {}\n", pc, instruction.encode(), instruction),
+            None => format!("{:03X}  ????\n", pc),
+        }
+    }
+
+    pub fn registers(chip8: &Chip8) -> String {
+        let registers = &chip8.registers;
+        let mut out = String::new();
+        for (index, value) in registers.v.iter().enumerate() {
+            let _ = write!(out, "V{:X}={:02X}", index, value);
+            out.push(if index % 8 == 7 { '\n' } else { ' ' });
+        }
+        let _ = writeln!(
+            out,
+            "I={:03X} PC={:03X} SP={:X} DT={:02X} ST={:02X}",
+            registers.i, registers.pc, registers.sp, registers.dt, registers.st
+        );
+        out
+    }
+
+    pub fn stack(chip8: &Chip8) -> String {
+        let sp = chip8.registers.sp as usize;
+        if sp == 0 {
+            return "stack is empty\n".to_string();
+        }
+        let mut out = String::new();
+        for (depth, addr) in chip8.chip8_stack.stack[..sp].iter().enumerate().rev() {
+            let _ = writeln!(out, "#{:X}  {:03X}", depth, addr);
+        }
+        out
+    }
+
+    pub fn memory(chip8: &Chip8, addr: usize, len: usize) -> String {
+        let memory = &chip8.chip8_memory.memory;
+        let end = addr.saturating_add(len).min(memory.len());
+        let mut out = String::new();
+        for row in (addr..end).step_by(16) {
+            let bytes = &memory[row..(row + 16).min(end)];
+            let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+            let _ = writeln!(out, "{:04X}  {}", row, hex.join(" "));
+        }
+        out
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Debugger::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::platform::Platform;
+
+    // A main loop that calls a three instruction subroutine and then spins
+    const PROGRAM: [u8; 12] = [
+        0x22, 0x06, // 200: CALL 206
+        0x60, 0x01, // 202: LD V0, 1
+        0x12, 0x04, // 204: JP 204
+        0x61, 0x05, // 206: LD V1, 5
+        0x62, 0x06, // 208: LD V2, 6
+        0x00, 0xEE, // 20A: RET
+    ];
+
+    fn paused_at_start() -> (Debugger, Chip8) {
+        let mut chip8 = Chip8::new();
+        chip8.chip8_load(&PROGRAM, PROGRAM.len()).unwrap();
+        let mut debugger = Debugger::new();
+        debugger.pause();
+        (debugger, chip8)
+    }
+
+    #[test]
+    fn commands_parse_hexadecimal_arguments() {
+        let (mut debugger, mut chip8) = paused_at_start();
+        assert_eq!(debugger.command(&mut chip8, "b 0x208"), "breakpoint set at 208\n");
+        assert_eq!(debugger.command(&mut chip8, "break 20a"), "breakpoint set at 20A\n");
+        assert!(debugger.breakpoints.contains(&0x208) && debugger.breakpoints.contains(&0x20A));
+        assert_eq!(debugger.command(&mut chip8, "d 208"), "breakpoint at 208 deleted\n");
+
+        debugger.command(&mut chip8, "watch 300 w");
+        debugger.command(&mut chip8, "watch vA 10");
+        debugger.command(&mut chip8, "w i");
+        assert_eq!(debugger.memory_watches.get(&0x300), Some(&WatchKind::Write));
+        assert_eq!(debugger.register_watches.get(&Register::V(0xA)), Some(&Some(0x10)));
+        assert_eq!(debugger.register_watches.get(&Register::I), Some(&None));
+
+        assert!(debugger.command(&mut chip8, "b 10000").starts_with("error: invalid address"));
+        assert!(debugger.command(&mut chip8, "step zz").starts_with("error: invalid count"));
+        assert!(debugger.command(&mut chip8, "watch 300 x").starts_with("error: invalid access kind"));
+        assert!(debugger.command(&mut chip8, "frob").starts_with("error: unknown command"));
+        assert_eq!(debugger.command(&mut chip8, "   "), "");
+    }
+
+    #[test]
+    fn step_runs_across_frames() {
+        let (mut debugger, mut chip8) = paused_at_start();
+        assert_eq!(debugger.command(&mut chip8, "step 3"), "");
+        assert_eq!(debugger.run(&mut chip8, 2).unwrap(), None);
+        assert_eq!(debugger.state, DebugState::Step { remaining: 1 });
+        let message = debugger.run(&mut chip8, 10).unwrap().unwrap();
+        assert!(debugger.is_paused());
+        assert_eq!(chip8.registers.pc, 0x20A);
+        assert!(message.starts_with("20A"));
+    }
+
+    #[test]
+    fn next_steps_over_a_call() {
+        let (mut debugger, mut chip8) = paused_at_start();
+        assert_eq!(debugger.command(&mut chip8, "next"), "");
+        let message = debugger.run(&mut chip8, 100).unwrap().unwrap();
+        assert!(debugger.is_paused());
+        assert_eq!(chip8.registers.pc, 0x202);
+        assert_eq!(chip8.registers.sp, 0);
+        assert_eq!(chip8.registers.v[1], 5);
+        assert_eq!(message, "202  6001  LD V0, 0x01\n");
+    }
+
+    #[test]
+    fn finish_returns_to_the_caller() {
+        let (mut debugger, mut chip8) = paused_at_start();
+        debugger.command(&mut chip8, "s");
+        debugger.run(&mut chip8, 100).unwrap();
+        assert_eq!(chip8.registers.pc, 0x206);
+        assert_eq!(chip8.registers.sp, 1);
+
+        assert_eq!(debugger.command(&mut chip8, "finish"), "");
+        debugger.run(&mut chip8, 100).unwrap().unwrap();
+        assert!(debugger.is_paused());
+        assert_eq!(chip8.registers.pc, 0x202);
+        assert_eq!(chip8.registers.sp, 0);
+        assert_eq!(chip8.registers.v[2], 6);
+        assert!(debugger.command(&mut chip8, "finish").starts_with("error: not inside a subroutine"));
+    }
+
+    #[test]
+    fn describe_shows_the_long_address() {
+        let mut chip8 = Chip8::with_platform(Platform::XoChip);
+        chip8.chip8_load(&[0xF0, 0x00, 0x12, 0x34], 4).unwrap();
+        assert_eq!(Debugger::describe(&chip8, 0x200), "200  F000 1234  LD I, 0x1234\n");
+    }
+}
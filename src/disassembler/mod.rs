@@ -0,0 +1,175 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use crate::config::CHIP8_PROGRAM_LOAD_ADDRESS;
+use crate::instruction::Instruction;
+use crate::platform::Platform;
+
+// One entry of a listing: either a decoded instruction or a single byte of data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LineKind {
+    // `operand` is the second word of XO-CHIP's four byte F000 NNNN
+    Code { instruction: Instruction, operand: Option<u16> },
+    Data(u8),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+    pub kind: LineKind,
+}
+
+// A disassembled ROM with the labels generated for CALL and JP targets
+#[derive(Debug, Clone, Default)]
+pub struct Disassembly {
+    pub lines: Vec<Line>,
+    pub labels: BTreeMap<u16, String>,
+}
+
+// Disassemble a ROM loaded at CHIP8_PROGRAM_LOAD_ADDRESS. Code is found by following
+// control flow from the entry point; every byte that is never reached is listed as data.
+pub fn disassemble(rom: &[u8], platform: Platform) -> Disassembly {
+    let start = CHIP8_PROGRAM_LOAD_ADDRESS;
+    let end = start + rom.len();
+    let byte_at = |addr: usize| -> Option<u8> {
+        if addr >= start && addr < end { Some(rom[addr - start]) } else { None }
+    };
+    let word_at = |addr: usize| -> Option<u16> {
+        Some((byte_at(addr)? as u16) << 8 | byte_at(addr + 1)? as u16)
+    };
+    let decode_at = |addr: usize| -> Option<Instruction> {
+        let instruction = Instruction::decode(word_at(addr)?).ok()?;
+        if instruction.platform() <= platform { Some(instruction) } else { None }
+    };
+
+    let mut code: BTreeMap<usize, Instruction> = BTreeMap::new();
+    let mut calls: BTreeSet<u16> = BTreeSet::new();
+    let mut jumps: BTreeSet<u16> = BTreeSet::new();
+    let mut pending = vec![start];
+
+    while let Some(addr) = pending.pop() {
+        if code.contains_key(&addr) {
+            continue;
+        }
+        let instruction = match decode_at(addr) {
+            Some(instruction) => instruction,
+            None => continue,
+        };
+        code.insert(addr, instruction);
+        let next = addr + instruction.size() as usize;
+
+        match instruction {
+            Instruction::Jump { nnn } => {
+                jumps.insert(nnn);
+                pending.push(nnn as usize);
+            },
+            Instruction::Call { nnn } => {
+                calls.insert(nnn);
+                pending.push(nnn as usize);
+                pending.push(next);
+            },
+            // The target of BNNN depends on a register so it can't be followed
+            Instruction::Ret | Instruction::Exit | Instruction::JumpOffset { .. } => (),
+            _ if instruction.is_skip() => {
+                pending.push(next);
+                let skipped = decode_at(next).map_or(2, |following| following.size() as usize);
+                pending.push(next + skipped);
+            },
+            _ => pending.push(next),
+        }
+    }
+
+    let mut lines = Vec::new();
+    let mut addr = start;
+    while addr < end {
+        // Instructions overlapping earlier code are shown as data
+        let instruction = code.get(&addr).copied()
+            .filter(|instruction| addr + instruction.size() as usize <= end);
+        match instruction {
+            Some(instruction) => {
+                let size = instruction.size() as usize;
+                let operand = match instruction {
+                    Instruction::LongSetI => word_at(addr + 2),
+                    _ => None,
+                };
+                lines.push(Line {
+                    addr: addr as u16,
+                    bytes: rom[addr - start..addr - start + size].to_vec(),
+                    kind: LineKind::Code { instruction, operand },
+                });
+                addr += size;
+            },
+            None => {
+                let byte = rom[addr - start];
+                lines.push(Line { addr: addr as u16, bytes: vec![byte], kind: LineKind::Data(byte) });
+                addr += 1;
+            },
+        }
+    }
+
+    // Only targets that start a line get a label, so every label is defined somewhere
+    // in the listing. Targets outside the ROM or inside another line stay as addresses.
+    let starts: BTreeSet<u16> = lines.iter().map(|line| line.addr).collect();
+    let mut labels = BTreeMap::new();
+    for &target in jumps.intersection(&starts) {
+        labels.insert(target, format!("label_{:03X}", target));
+    }
+    // Subroutine names win over plain labels when an address is both
+    for &target in calls.intersection(&starts) {
+        labels.insert(target, format!("sub_{:03X}", target));
+    }
+
+    Disassembly { lines, labels }
+}
+
+impl Disassembly {
+    // Format a line's mnemonic, replacing branch targets with their labels
+    pub fn mnemonic(&self, line: &Line) -> String {
+        let label = |nnn: u16| self.labels.get(&nnn).cloned().unwrap_or_else(|| format!("{:#05x}", nnn));
+        match line.kind {
+            LineKind::Code { instruction: Instruction::Jump { nnn }, .. } => format!("JP {}", label(nnn)),
+            LineKind::Code { instruction: Instruction::Call { nnn }, .. } => format!("CALL {}", label(nnn)),
+            LineKind::Code { instruction: Instruction::LongSetI, operand: Some(nnnn) } => {
+                format!("LD I, {:#06x}", nnnn)
+            },
+            LineKind::Code { instruction, .. } => instruction.to_string(),
+            LineKind::Data(byte) => {
+                // Show data as a sprite row so graphics are recognisable in the listing
+                let row: String = (0..8).map(|bit| if byte & (0x80 >> bit) != 0 { '#' } else { '.' }).collect();
+                format!("DB {:#04x}    ; {}", byte, row)
+            },
+        }
+    }
+}
+
+impl fmt::Display for Disassembly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.lines {
+            if let Some(label) = self.labels.get(&line.addr) {
+                writeln!(f, "{}:", label)?;
+            }
+            let raw: String = line.bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+            writeln!(f, "    {:03X}  {:<8}  {}", line.addr, raw, self.mnemonic(line))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_only_for_listed_lines() {
+        // CALL 204 and JP 300, outside the ROM. The subroutine is F000 0000 followed
+        // by JP 205, into the middle of it.
+        let rom = [0x22, 0x04, 0x13, 0x00, 0xF0, 0x00, 0x00, 0x00, 0x12, 0x05];
+        let listing = disassemble(&rom, Platform::XoChip);
+        assert_eq!(listing.labels.get(&0x204).map(String::as_str), Some("sub_204"));
+        assert!(!listing.labels.contains_key(&0x300));
+        assert!(!listing.labels.contains_key(&0x205));
+        let text = listing.to_string();
+        assert!(text.contains("JP 0x300"));
+        assert!(text.contains("JP 0x205"));
+    }
+}
@@ -0,0 +1,127 @@
+use std::fmt;
+
+// Kind of memory access that caused a fault
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryAccess {
+    Fetch,
+    Read,
+    Write,
+}
+
+// Errors that stop the interpreter from executing the current instruction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Chip8Error {
+    InvalidOpcode { pc: u16, op: u16 },
+    StackOverflow { pc: u16 },
+    StackUnderflow { pc: u16 },
+    MemoryOutOfBounds { addr: usize, access: MemoryAccess },
+    // The program doesn't fit between the load address and the end of memory
+    RomTooLarge { size: usize, capacity: usize },
+}
+
+// An opcode that does not correspond to any known instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeError {
+    pub op: u16,
+}
+
+// A problem found while assembling Octo source, with the 1-based line it occurred on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssembleError {
+    pub line: usize,
+    pub message: String,
+}
+
+// A problem reading a movie file, with the line it was found on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MovieError {
+    pub line: usize,
+    pub message: String,
+}
+
+// Reasons a save state can't be restored
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SaveStateError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    ChecksumMismatch,
+    Truncated,
+    Invalid(String),
+}
+
+// What happened during a single successful tick
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepOutcome {
+    Executed,
+    WaitingForKey,
+    WaitingForVBlank,
+    Exited,
+}
+
+impl fmt::Display for MemoryAccess {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MemoryAccess::Fetch => write!(f, "fetch"),
+            MemoryAccess::Read => write!(f, "read"),
+            MemoryAccess::Write => write!(f, "write"),
+        }
+    }
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Chip8Error::InvalidOpcode { pc, op } => {
+                write!(f, "invalid opcode {:#06x} at {:#05x}", op, pc)
+            },
+            Chip8Error::StackOverflow { pc } => write!(f, "stack overflow at {:#05x}", pc),
+            Chip8Error::StackUnderflow { pc } => write!(f, "stack underflow at {:#05x}", pc),
+            Chip8Error::MemoryOutOfBounds { addr, access } => {
+                write!(f, "memory {} out of bounds at {:#06x}", access, addr)
+            },
+            Chip8Error::RomTooLarge { size, capacity } => {
+                write!(f, "ROM is {} bytes but only {} bytes fit in memory", size, capacity)
+            },
+        }
+    }
+}
+
+impl std::error::Error for Chip8Error {}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown opcode {:#06x}", self.op)
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AssembleError {}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for MovieError {}
+
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveStateError::BadMagic => write!(f, "not a save state"),
+            SaveStateError::UnsupportedVersion(version) => write!(f, "unsupported save state version {}", version),
+            SaveStateError::ChecksumMismatch => write!(f, "save state is corrupted"),
+            SaveStateError::Truncated => write!(f, "save state is truncated"),
+            SaveStateError::Invalid(reason) => write!(f, "invalid save state: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for SaveStateError {}
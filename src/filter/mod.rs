@@ -0,0 +1,312 @@
+use std::io::Write;
+use crate::screen::Chip8Screen;
+
+// Scale factor of the xBR style smoother
+const XBR_SCALE: usize = 4;
+// How bright the dark half of a scanline and the lines of the grid are
+const SCANLINE_LEVEL: f32 = 0.5;
+const GRID_LEVEL: f32 = 0.6;
+// How far the CRT's corners bend in, as a share of the picture
+const CRT_CURVATURE: f32 = 0.06;
+
+pub const FILTER_NAMES: [&str; 6] = ["scale2x", "scale3x", "xbr", "scanlines", "grid", "crt"];
+
+// An RGB picture of the screen on its way through the filters
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    // Row by row
+    pub pixels: Vec<[u8; 3]>,
+    // How many pixels across one CHIP-8 pixel is
+    pub cell: usize,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize, pixels: Vec<[u8; 3]>) -> Self {
+        Image { width, height, pixels, cell: 1 }
+    }
+
+    // The visible screen, one pixel per CHIP-8 pixel
+    pub fn from_screen(screen: &Chip8Screen, palette: &[[u8; 3]; 4]) -> Self {
+        let (width, height) = (screen.width(), screen.height());
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                pixels.push(palette[screen.pixel(x, y) as usize]);
+            }
+        }
+        Image::new(width, height, pixels)
+    }
+
+    // The pixel `dx`, `dy` away from `x`, `y`, repeating the edges outside the picture
+    fn get(&self, x: usize, y: usize, dx: isize, dy: isize) -> [u8; 3] {
+        let x = (x as isize + dx).clamp(0, self.width as isize - 1) as usize;
+        let y = (y as isize + dy).clamp(0, self.height as isize - 1) as usize;
+        self.pixels[y * self.width + x]
+    }
+
+    // Every pixel drawn as a `scale` x `scale` square
+    pub fn resize(&self, scale: usize) -> Image {
+        if scale == 1 {
+            return self.clone();
+        }
+        let width = self.width * scale;
+        let mut pixels = Vec::with_capacity(width * self.height * scale);
+        for row in self.pixels.chunks(self.width) {
+            for _ in 0..scale {
+                for &pixel in row {
+                    pixels.extend(std::iter::repeat_n(pixel, scale));
+                }
+            }
+        }
+        Image { width, height: self.height * scale, pixels, cell: self.cell * scale }
+    }
+
+    pub fn to_rgb(&self) -> Vec<u8> {
+        self.pixels.as_flattened().to_vec()
+    }
+
+    pub fn write_png<W: Write>(&self, writer: W) -> Result<(), png::EncodingError> {
+        let mut encoder = png::Encoder::new(writer, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut png = encoder.write_header()?;
+        png.write_image_data(&self.to_rgb())?;
+        png.finish()
+    }
+
+    // Build a picture `scale` times the size of this one, one block of pixels at a time
+    fn expand<F: Fn(&Image, usize, usize, usize, usize) -> [u8; 3]>(&self, scale: usize, pixel: F) -> Image {
+        let width = self.width * scale;
+        let height = self.height * scale;
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                pixels.push(pixel(self, x / scale, y / scale, x % scale, y % scale));
+            }
+        }
+        Image { width, height, pixels, cell: self.cell * scale }
+    }
+
+    // Same size, with each pixel changed by `pixel`
+    fn map<F: Fn(usize, usize, [u8; 3]) -> [u8; 3]>(&self, pixel: F) -> Image {
+        let pixels = self.pixels.iter().enumerate()
+            .map(|(i, &colour)| pixel(i % self.width, i / self.width, colour))
+            .collect();
+        Image { pixels, ..self.clone() }
+    }
+}
+
+// Post-processing for the window, screenshots and recordings. Filters run on the
+// CPU one after the other, each on the picture the previous one made.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    // EPX / AdvMAME2x: doubles the size, rounding off the corners of staircases
+    Scale2x,
+    // AdvMAME3x, the same at three times the size
+    Scale3x,
+    // A simplified xBR: four times the size, with 45 degree edges drawn as smooth
+    // anti-aliased lines instead of steps
+    Xbr,
+    // Darkens the lower half of every row of CHIP-8 pixels
+    Scanlines,
+    // Dark lines between CHIP-8 pixels, like an LCD
+    Grid,
+    // Curved glass with darker corners
+    Crt,
+}
+
+impl Filter {
+    pub fn from_name(name: &str) -> Option<Filter> {
+        match name.to_ascii_lowercase().as_str() {
+            "scale2x" | "epx" => Some(Filter::Scale2x),
+            "scale3x" => Some(Filter::Scale3x),
+            "xbr" => Some(Filter::Xbr),
+            "scanlines" => Some(Filter::Scanlines),
+            "grid" => Some(Filter::Grid),
+            "crt" => Some(Filter::Crt),
+            _ => None,
+        }
+    }
+
+    // How many times bigger the picture comes out, for a CHIP-8 pixel `cell` pixels across
+    fn scale(self, cell: usize) -> usize {
+        match self {
+            Filter::Scale2x => 2,
+            Filter::Scale3x => 3,
+            Filter::Xbr => XBR_SCALE,
+            // The overlays need room to draw in, small pictures are blown up first
+            overlay => overlay.min_cell().div_ceil(cell),
+        }
+    }
+
+    fn min_cell(self) -> usize {
+        match self {
+            Filter::Scanlines => 2,
+            Filter::Grid => 3,
+            Filter::Crt => 4,
+            _ => 1,
+        }
+    }
+
+    pub fn apply(self, image: &Image) -> Image {
+        let resized;
+        let image = match self.min_cell().div_ceil(image.cell) {
+            scale if scale > 1 => {
+                resized = image.resize(scale);
+                &resized
+            },
+            _ => image,
+        };
+        match self {
+            Filter::Scale2x => image.expand(2, scale2x),
+            Filter::Scale3x => image.expand(3, scale3x),
+            Filter::Xbr => image.expand(XBR_SCALE, xbr),
+            Filter::Scanlines => {
+                let cell = image.cell;
+                image.map(|_, y, colour| if y % cell >= cell.div_ceil(2) { dim(colour, SCANLINE_LEVEL) } else { colour })
+            },
+            Filter::Grid => {
+                let cell = image.cell;
+                image.map(|x, y, colour| if x % cell == cell - 1 || y % cell == cell - 1 { dim(colour, GRID_LEVEL) } else { colour })
+            },
+            Filter::Crt => crt(image),
+        }
+    }
+}
+
+// Run the screen through `filters`
+pub fn render(screen: &Chip8Screen, palette: &[[u8; 3]; 4], filters: &[Filter]) -> Image {
+    apply(Image::from_screen(screen, palette), filters)
+}
+
+pub fn apply(image: Image, filters: &[Filter]) -> Image {
+    filters.iter().fold(image, |image, filter| filter.apply(&image))
+}
+
+// How many times bigger than the screen `filters` make the picture
+pub fn scale(filters: &[Filter]) -> usize {
+    filters.iter().fold(1, |cell, filter| cell * filter.scale(cell))
+}
+
+fn dim(colour: [u8; 3], level: f32) -> [u8; 3] {
+    colour.map(|channel| (channel as f32 * level).round() as u8)
+}
+
+fn mix(a: [u8; 3], b: [u8; 3], amount: f32) -> [u8; 3] {
+    let mut colour = [0; 3];
+    for (channel, (a, b)) in colour.iter_mut().zip(a.into_iter().zip(b)) {
+        *channel = (a as f32 + (b as f32 - a as f32) * amount).round() as u8;
+    }
+    colour
+}
+
+// The neighbours of a pixel, named as in the Scale2x description:
+//     A B C
+//     D E F
+//     G H I
+struct Neighbours {
+    a: [u8; 3], b: [u8; 3], c: [u8; 3],
+    d: [u8; 3], e: [u8; 3], f: [u8; 3],
+    g: [u8; 3], h: [u8; 3], i: [u8; 3],
+}
+
+impl Neighbours {
+    fn of(image: &Image, x: usize, y: usize) -> Self {
+        Neighbours {
+            a: image.get(x, y, -1, -1), b: image.get(x, y, 0, -1), c: image.get(x, y, 1, -1),
+            d: image.get(x, y, -1, 0), e: image.get(x, y, 0, 0), f: image.get(x, y, 1, 0),
+            g: image.get(x, y, -1, 1), h: image.get(x, y, 0, 1), i: image.get(x, y, 1, 1),
+        }
+    }
+
+    // Whether the edges meeting at each corner join up, making a diagonal across it
+    fn top_left(&self) -> bool {
+        self.d == self.b && self.b != self.f && self.d != self.h
+    }
+
+    fn top_right(&self) -> bool {
+        self.b == self.f && self.b != self.d && self.f != self.h
+    }
+
+    fn bottom_left(&self) -> bool {
+        self.d == self.h && self.d != self.b && self.h != self.f
+    }
+
+    fn bottom_right(&self) -> bool {
+        self.h == self.f && self.d != self.h && self.b != self.f
+    }
+}
+
+fn scale2x(image: &Image, x: usize, y: usize, sub_x: usize, sub_y: usize) -> [u8; 3] {
+    let n = Neighbours::of(image, x, y);
+    match (sub_x, sub_y) {
+        (0, 0) if n.top_left() => n.d,
+        (1, 0) if n.top_right() => n.f,
+        (0, 1) if n.bottom_left() => n.d,
+        (1, 1) if n.bottom_right() => n.f,
+        _ => n.e,
+    }
+}
+
+fn scale3x(image: &Image, x: usize, y: usize, sub_x: usize, sub_y: usize) -> [u8; 3] {
+    let n = Neighbours::of(image, x, y);
+    match (sub_x, sub_y) {
+        (0, 0) if n.top_left() => n.d,
+        (1, 0) if (n.top_left() && n.e != n.c) || (n.top_right() && n.e != n.a) => n.b,
+        (2, 0) if n.top_right() => n.f,
+        (0, 1) if (n.top_left() && n.e != n.g) || (n.bottom_left() && n.e != n.a) => n.d,
+        (2, 1) if (n.top_right() && n.e != n.i) || (n.bottom_right() && n.e != n.c) => n.f,
+        (0, 2) if n.bottom_left() => n.d,
+        (1, 2) if (n.bottom_left() && n.e != n.i) || (n.bottom_right() && n.e != n.g) => n.h,
+        (2, 2) if n.bottom_right() => n.f,
+        _ => n.e,
+    }
+}
+
+// Where Scale2x would fill a whole corner, fill only the triangle cut off by a line
+// joining the middles of the corner's two sides. Both sides of a step get one, so
+// the steps in a 45 degree edge become a straight line. The share of each output
+// pixel that falls past the line sets how much of the neighbouring colour it gets.
+fn xbr(image: &Image, x: usize, y: usize, sub_x: usize, sub_y: usize) -> [u8; 3] {
+    let n = Neighbours::of(image, x, y);
+    let size = 1.0 / XBR_SCALE as f32;
+    let u = (sub_x as f32 + 0.5) * size;
+    let v = (sub_y as f32 + 0.5) * size;
+    // How far along the diagonal from each corner, as u + v measured from that corner
+    let corners = [
+        (n.top_left(), n.d, u + v),
+        (n.top_right(), n.f, (1.0 - u) + v),
+        (n.bottom_left(), n.d, u + (1.0 - v)),
+        (n.bottom_right(), n.f, (1.0 - u) + (1.0 - v)),
+    ];
+    for (joined, colour, distance) in corners {
+        let coverage = ((0.5 - distance) / (2.0 * size) + 0.5).clamp(0.0, 1.0);
+        if joined && coverage > 0.0 {
+            return mix(n.e, colour, coverage);
+        }
+    }
+    n.e
+}
+
+// Bend the picture as if on the curved glass of a CRT, black outside it, and fade
+// out towards the corners
+fn crt(image: &Image) -> Image {
+    let (width, height) = (image.width as f32, image.height as f32);
+    image.map(|x, y, _| {
+        // -1 to 1 across the picture
+        let nx = (x as f32 + 0.5) / width * 2.0 - 1.0;
+        let ny = (y as f32 + 0.5) / height * 2.0 - 1.0;
+        let sx = nx * (1.0 + CRT_CURVATURE * ny * ny);
+        let sy = ny * (1.0 + CRT_CURVATURE * nx * nx);
+        if sx.abs() > 1.0 || sy.abs() > 1.0 {
+            return [0, 0, 0];
+        }
+        let (u, v) = ((sx + 1.0) / 2.0, (sy + 1.0) / 2.0);
+        let source_x = ((u * width) as usize).min(image.width - 1);
+        let source_y = ((v * height) as usize).min(image.height - 1);
+        let vignette = (16.0 * u * (1.0 - u) * v * (1.0 - v)).powf(0.25);
+        dim(image.pixels[source_y * image.width + source_x], vignette)
+    })
+}
@@ -0,0 +1,109 @@
+use crate::config::{CHIP_8_AUDIO_PATTERN_SIZE, CHIP_8_DEFAULT_TONE_FREQUENCY, CHIP_8_DEFAULT_VOLUME};
+use crate::screen::Chip8Screen;
+
+// The interfaces between the interpreter and whatever shows it to the user. The
+// core only talks to these, so it can run without SDL or an audio device.
+
+// What the sound timer wants to play
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tone {
+    // The classic CHIP-8 buzzer
+    Beep,
+    // XO-CHIP 1-bit pattern played back at a rate set by the pitch register
+    Pattern { pattern: [u8; CHIP_8_AUDIO_PATTERN_SIZE], pitch: u8 },
+}
+
+// Shape of the sound timer's buzzer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Waveform {
+    #[default]
+    Square,
+    Sine,
+    Triangle,
+}
+
+impl Waveform {
+    pub fn from_name(name: &str) -> Option<Waveform> {
+        match name.to_ascii_lowercase().as_str() {
+            "square" => Some(Waveform::Square),
+            "sine" => Some(Waveform::Sine),
+            "triangle" => Some(Waveform::Triangle),
+            _ => None,
+        }
+    }
+
+    // Level at a point in the cycle, `phase` running from 0 to 1
+    pub fn sample(self, phase: f32) -> f32 {
+        match self {
+            Waveform::Square => if phase < 0.5 { 1.0 } else { -1.0 },
+            Waveform::Sine => (phase * std::f32::consts::TAU).sin(),
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+        }
+    }
+}
+
+// How the buzzer sounds. XO-CHIP patterns ignore the frequency and waveform.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AudioSettings {
+    pub frequency: f32,
+    pub waveform: Waveform,
+    // 0 is silent, 1 is full scale
+    pub volume: f32,
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        AudioSettings {
+            frequency: CHIP_8_DEFAULT_TONE_FREQUENCY,
+            waveform: Waveform::Square,
+            volume: CHIP_8_DEFAULT_VOLUME,
+        }
+    }
+}
+
+pub trait Audio {
+    // Called on every 60 Hz timer tick with the tone to play until the next tick,
+    // or None once the sound timer has run out. Must not block.
+    fn set_tone(&mut self, tone: Option<Tone>);
+}
+
+pub trait Display {
+    // Show a finished frame
+    fn present(&mut self, screen: &Chip8Screen) -> Result<(), String>;
+    fn set_title(&mut self, title: &str) -> Result<(), String>;
+}
+
+// Things the user asked for since the last poll
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputEvent {
+    KeyDown(u8),
+    KeyUp(u8),
+    Quit,
+    SaveState(u8),
+    LoadState(u8),
+    TogglePause,
+    // The rewind button was pressed or released
+    Rewind(bool),
+    // Save the screen to a PNG, at the window's scale or at native resolution
+    Screenshot { scaled: bool },
+    // Start or stop recording a clip
+    ToggleCapture,
+    // Switch to the next colour theme
+    NextTheme,
+    // Turn the phosphor fade filter on or off
+    TogglePhosphor,
+    // Switch between a window and fullscreen
+    ToggleFullscreen,
+}
+
+pub trait Input {
+    fn poll(&mut self) -> Vec<InputEvent>;
+}
+
+// Audio that plays nothing, used unless a frontend provides a device
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Silence;
+
+impl Audio for Silence {
+    fn set_tone(&mut self, _tone: Option<Tone>) {}
+}
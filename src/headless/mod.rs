@@ -0,0 +1,93 @@
+use crate::chip8::Chip8;
+use crate::config::CHIP8_TICKS_PER_FRAME;
+use crate::error::{Chip8Error, StepOutcome};
+use crate::movie::KeyEvent;
+
+// How long to run a ROM without a window, and what to feed it
+#[derive(Debug, Clone)]
+pub struct HeadlessOptions {
+    // Upper limit on the number of frames to run
+    pub frames: u32,
+    pub ticks_per_frame: usize,
+    // Stop before executing the instruction at this address
+    pub until_pc: Option<u16>,
+    // Stop before executing this opcode
+    pub until_opcode: Option<u16>,
+    // Scripted key presses and releases, applied at the start of their frame
+    pub keys: Vec<KeyEvent>,
+}
+
+impl Default for HeadlessOptions {
+    fn default() -> Self {
+        HeadlessOptions {
+            frames: 0,
+            ticks_per_frame: CHIP8_TICKS_PER_FRAME,
+            until_pc: None,
+            until_opcode: None,
+            keys: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    Frames,
+    Pc(u16),
+    Opcode { pc: u16, op: u16 },
+    // The program ran 00FD
+    Exited,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeadlessReport {
+    // Frames started, including the one that stopped early
+    pub frames: u32,
+    pub reason: StopReason,
+}
+
+// Run a loaded ROM frame by frame without any frontend
+pub fn run(chip8: &mut Chip8, options: &HeadlessOptions) -> Result<HeadlessReport, Chip8Error> {
+    run_with(chip8, options, |_| ())
+}
+
+// Like `run`, calling `on_frame` at the end of every whole frame, before the timers tick
+pub fn run_with<F: FnMut(&Chip8)>(chip8: &mut Chip8, options: &HeadlessOptions, mut on_frame: F) -> Result<HeadlessReport, Chip8Error> {
+    let mut keys = options.keys.clone();
+    keys.sort_by_key(|event| event.frame);
+    let mut keys = keys.into_iter().peekable();
+
+    for frame in 0..options.frames {
+        while let Some(event) = keys.next_if(|event| event.frame <= frame) {
+            chip8.chip8_keyboard.keyboard[event.key as usize] = event.down;
+        }
+
+        for _ in 0..options.ticks_per_frame {
+            if let Some(reason) = stop_condition(chip8, options) {
+                return Ok(HeadlessReport { frames: frame + 1, reason });
+            }
+            match chip8.tick()? {
+                StepOutcome::Exited => return Ok(HeadlessReport { frames: frame + 1, reason: StopReason::Exited }),
+                StepOutcome::WaitingForVBlank => break,
+                StepOutcome::Executed | StepOutcome::WaitingForKey => (),
+            }
+        }
+        on_frame(chip8);
+        chip8.tick_timers();
+    }
+    Ok(HeadlessReport { frames: options.frames, reason: StopReason::Frames })
+}
+
+fn stop_condition(chip8: &Chip8, options: &HeadlessOptions) -> Option<StopReason> {
+    let pc = chip8.registers.pc;
+    if options.until_pc == Some(pc) {
+        return Some(StopReason::Pc(pc));
+    }
+    let memory = &chip8.chip8_memory.memory;
+    if let (Some(until), Some(&high), Some(&low)) = (options.until_opcode, memory.get(pc as usize), memory.get(pc as usize + 1)) {
+        let op = (high as u16) << 8 | low as u16;
+        if op == until {
+            return Some(StopReason::Opcode { pc, op });
+        }
+    }
+    None
+}
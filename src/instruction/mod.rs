@@ -0,0 +1,354 @@
+use std::fmt;
+use crate::error::DecodeError;
+use crate::platform::Platform;
+
+// A decoded instruction. Register operands are indices 0x0-0xF, `nn` is an 8 bit
+// immediate and `nnn` a 12 bit address. The set covers CHIP-8, SUPER-CHIP and XO-CHIP;
+// use `platform` to find out which dialect introduced an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    Nop,                          // 0000
+    ScrollDown { n: u8 },         // 00CN
+    ScrollUp { n: u8 },           // 00DN
+    Cls,                          // 00E0
+    Ret,                          // 00EE
+    ScrollRight,                  // 00FB
+    ScrollLeft,                   // 00FC
+    Exit,                         // 00FD
+    Lores,                        // 00FE
+    Hires,                        // 00FF
+    Jump { nnn: u16 },            // 1NNN
+    Call { nnn: u16 },            // 2NNN
+    SkipEqImm { x: u8, nn: u8 },  // 3XNN
+    SkipNeImm { x: u8, nn: u8 },  // 4XNN
+    SkipEq { x: u8, y: u8 },      // 5XY0
+    StoreRange { x: u8, y: u8 },  // 5XY2
+    LoadRange { x: u8, y: u8 },   // 5XY3
+    SetImm { x: u8, nn: u8 },     // 6XNN
+    AddImm { x: u8, nn: u8 },     // 7XNN
+    Set { x: u8, y: u8 },         // 8XY0
+    Or { x: u8, y: u8 },          // 8XY1
+    And { x: u8, y: u8 },         // 8XY2
+    Xor { x: u8, y: u8 },         // 8XY3
+    Add { x: u8, y: u8 },         // 8XY4
+    Sub { x: u8, y: u8 },         // 8XY5
+    Shr { x: u8, y: u8 },         // 8XY6
+    SubN { x: u8, y: u8 },        // 8XY7
+    Shl { x: u8, y: u8 },         // 8XYE
+    SkipNe { x: u8, y: u8 },      // 9XY0
+    SetI { nnn: u16 },            // ANNN
+    JumpOffset { nnn: u16 },      // BNNN, or BXNN with the jump_vx quirk
+    Random { x: u8, nn: u8 },     // CXNN
+    Draw { x: u8, y: u8, n: u8 }, // DXYN
+    SkipKey { x: u8 },            // EX9E
+    SkipNotKey { x: u8 },         // EXA1
+    LongSetI,                     // F000 NNNN, the address is the following word
+    SelectPlanes { n: u8 },       // FN01
+    LoadAudio,                    // F002
+    GetDelay { x: u8 },           // FX07
+    WaitKey { x: u8 },            // FX0A
+    SetDelay { x: u8 },           // FX15
+    SetSound { x: u8 },           // FX18
+    AddI { x: u8 },               // FX1E
+    Font { x: u8 },               // FX29
+    BigFont { x: u8 },            // FX30
+    Bcd { x: u8 },                // FX33
+    Pitch { x: u8 },              // FX3A
+    Store { x: u8 },              // FX55
+    Load { x: u8 },               // FX65
+    StoreFlags { x: u8 },         // FX75
+    LoadFlags { x: u8 },          // FX85
+}
+
+impl Instruction {
+    pub fn decode(op: u16) -> Result<Instruction, DecodeError> {
+        let digit1 = (op & 0xF000) >> 12;
+        let digit2 = (op & 0x0F00) >> 8;
+        let digit3 = (op & 0x00F0) >> 4;
+        let digit4 = op & 0x000F;
+
+        let x = digit2 as u8;
+        let y = digit3 as u8;
+        let n = digit4 as u8;
+        let nn = (op & 0xFF) as u8;
+        let nnn = op & 0xFFF;
+
+        let instruction = match (digit1, digit2, digit3, digit4) {
+            (0, 0, 0, 0) => Instruction::Nop,
+            (0, 0, 0xC, _) => Instruction::ScrollDown { n },
+            (0, 0, 0xD, _) => Instruction::ScrollUp { n },
+            (0, 0, 0xE, 0) => Instruction::Cls,
+            (0, 0, 0xE, 0xE) => Instruction::Ret,
+            (0, 0, 0xF, 0xB) => Instruction::ScrollRight,
+            (0, 0, 0xF, 0xC) => Instruction::ScrollLeft,
+            (0, 0, 0xF, 0xD) => Instruction::Exit,
+            (0, 0, 0xF, 0xE) => Instruction::Lores,
+            (0, 0, 0xF, 0xF) => Instruction::Hires,
+            (1, _, _, _) => Instruction::Jump { nnn },
+            (2, _, _, _) => Instruction::Call { nnn },
+            (3, _, _, _) => Instruction::SkipEqImm { x, nn },
+            (4, _, _, _) => Instruction::SkipNeImm { x, nn },
+            (5, _, _, 0) => Instruction::SkipEq { x, y },
+            (5, _, _, 2) => Instruction::StoreRange { x, y },
+            (5, _, _, 3) => Instruction::LoadRange { x, y },
+            (6, _, _, _) => Instruction::SetImm { x, nn },
+            (7, _, _, _) => Instruction::AddImm { x, nn },
+            (8, _, _, 0) => Instruction::Set { x, y },
+            (8, _, _, 1) => Instruction::Or { x, y },
+            (8, _, _, 2) => Instruction::And { x, y },
+            (8, _, _, 3) => Instruction::Xor { x, y },
+            (8, _, _, 4) => Instruction::Add { x, y },
+            (8, _, _, 5) => Instruction::Sub { x, y },
+            (8, _, _, 6) => Instruction::Shr { x, y },
+            (8, _, _, 7) => Instruction::SubN { x, y },
+            (8, _, _, 0xE) => Instruction::Shl { x, y },
+            (9, _, _, 0) => Instruction::SkipNe { x, y },
+            (0xA, _, _, _) => Instruction::SetI { nnn },
+            (0xB, _, _, _) => Instruction::JumpOffset { nnn },
+            (0xC, _, _, _) => Instruction::Random { x, nn },
+            (0xD, _, _, _) => Instruction::Draw { x, y, n },
+            (0xE, _, 9, 0xE) => Instruction::SkipKey { x },
+            (0xE, _, 0xA, 1) => Instruction::SkipNotKey { x },
+            (0xF, 0, 0, 0) => Instruction::LongSetI,
+            (0xF, _, 0, 1) => Instruction::SelectPlanes { n: x },
+            (0xF, 0, 0, 2) => Instruction::LoadAudio,
+            (0xF, _, 0, 7) => Instruction::GetDelay { x },
+            (0xF, _, 0, 0xA) => Instruction::WaitKey { x },
+            (0xF, _, 1, 5) => Instruction::SetDelay { x },
+            (0xF, _, 1, 8) => Instruction::SetSound { x },
+            (0xF, _, 1, 0xE) => Instruction::AddI { x },
+            (0xF, _, 2, 9) => Instruction::Font { x },
+            (0xF, _, 3, 0) => Instruction::BigFont { x },
+            (0xF, _, 3, 3) => Instruction::Bcd { x },
+            (0xF, _, 3, 0xA) => Instruction::Pitch { x },
+            (0xF, _, 5, 5) => Instruction::Store { x },
+            (0xF, _, 6, 5) => Instruction::Load { x },
+            (0xF, _, 7, 5) => Instruction::StoreFlags { x },
+            (0xF, _, 8, 5) => Instruction::LoadFlags { x },
+            (_, _, _, _) => return Err(DecodeError { op }),
+        };
+        Ok(instruction)
+    }
+
+    pub fn encode(&self) -> u16 {
+        // Assemble an opcode from its four nibbles
+        fn nibbles(a: u16, b: u8, c: u8, d: u8) -> u16 {
+            a << 12 | ((b & 0xF) as u16) << 8 | ((c & 0xF) as u16) << 4 | (d & 0xF) as u16
+        }
+        fn with_byte(a: u16, x: u8, nn: u8) -> u16 {
+            a << 12 | ((x & 0xF) as u16) << 8 | nn as u16
+        }
+        fn with_addr(a: u16, nnn: u16) -> u16 {
+            a << 12 | (nnn & 0xFFF)
+        }
+
+        match *self {
+            Instruction::Nop => 0x0000,
+            Instruction::ScrollDown { n } => 0x00C0 | (n & 0xF) as u16,
+            Instruction::ScrollUp { n } => 0x00D0 | (n & 0xF) as u16,
+            Instruction::Cls => 0x00E0,
+            Instruction::Ret => 0x00EE,
+            Instruction::ScrollRight => 0x00FB,
+            Instruction::ScrollLeft => 0x00FC,
+            Instruction::Exit => 0x00FD,
+            Instruction::Lores => 0x00FE,
+            Instruction::Hires => 0x00FF,
+            Instruction::Jump { nnn } => with_addr(1, nnn),
+            Instruction::Call { nnn } => with_addr(2, nnn),
+            Instruction::SkipEqImm { x, nn } => with_byte(3, x, nn),
+            Instruction::SkipNeImm { x, nn } => with_byte(4, x, nn),
+            Instruction::SkipEq { x, y } => nibbles(5, x, y, 0),
+            Instruction::StoreRange { x, y } => nibbles(5, x, y, 2),
+            Instruction::LoadRange { x, y } => nibbles(5, x, y, 3),
+            Instruction::SetImm { x, nn } => with_byte(6, x, nn),
+            Instruction::AddImm { x, nn } => with_byte(7, x, nn),
+            Instruction::Set { x, y } => nibbles(8, x, y, 0),
+            Instruction::Or { x, y } => nibbles(8, x, y, 1),
+            Instruction::And { x, y } => nibbles(8, x, y, 2),
+            Instruction::Xor { x, y } => nibbles(8, x, y, 3),
+            Instruction::Add { x, y } => nibbles(8, x, y, 4),
+            Instruction::Sub { x, y } => nibbles(8, x, y, 5),
+            Instruction::Shr { x, y } => nibbles(8, x, y, 6),
+            Instruction::SubN { x, y } => nibbles(8, x, y, 7),
+            Instruction::Shl { x, y } => nibbles(8, x, y, 0xE),
+            Instruction::SkipNe { x, y } => nibbles(9, x, y, 0),
+            Instruction::SetI { nnn } => with_addr(0xA, nnn),
+            Instruction::JumpOffset { nnn } => with_addr(0xB, nnn),
+            Instruction::Random { x, nn } => with_byte(0xC, x, nn),
+            Instruction::Draw { x, y, n } => nibbles(0xD, x, y, n),
+            Instruction::SkipKey { x } => with_byte(0xE, x, 0x9E),
+            Instruction::SkipNotKey { x } => with_byte(0xE, x, 0xA1),
+            Instruction::LongSetI => 0xF000,
+            Instruction::SelectPlanes { n } => with_byte(0xF, n, 0x01),
+            Instruction::LoadAudio => 0xF002,
+            Instruction::GetDelay { x } => with_byte(0xF, x, 0x07),
+            Instruction::WaitKey { x } => with_byte(0xF, x, 0x0A),
+            Instruction::SetDelay { x } => with_byte(0xF, x, 0x15),
+            Instruction::SetSound { x } => with_byte(0xF, x, 0x18),
+            Instruction::AddI { x } => with_byte(0xF, x, 0x1E),
+            Instruction::Font { x } => with_byte(0xF, x, 0x29),
+            Instruction::BigFont { x } => with_byte(0xF, x, 0x30),
+            Instruction::Bcd { x } => with_byte(0xF, x, 0x33),
+            Instruction::Pitch { x } => with_byte(0xF, x, 0x3A),
+            Instruction::Store { x } => with_byte(0xF, x, 0x55),
+            Instruction::Load { x } => with_byte(0xF, x, 0x65),
+            Instruction::StoreFlags { x } => with_byte(0xF, x, 0x75),
+            Instruction::LoadFlags { x } => with_byte(0xF, x, 0x85),
+        }
+    }
+
+    // The earliest platform that understands this instruction
+    pub fn platform(&self) -> Platform {
+        match self {
+            Instruction::ScrollDown { .. }
+            | Instruction::ScrollRight
+            | Instruction::ScrollLeft
+            | Instruction::Exit
+            | Instruction::Lores
+            | Instruction::Hires
+            | Instruction::BigFont { .. }
+            | Instruction::StoreFlags { .. }
+            | Instruction::LoadFlags { .. } => Platform::SuperChip,
+            Instruction::ScrollUp { .. }
+            | Instruction::StoreRange { .. }
+            | Instruction::LoadRange { .. }
+            | Instruction::LongSetI
+            | Instruction::SelectPlanes { .. }
+            | Instruction::LoadAudio
+            | Instruction::Pitch { .. } => Platform::XoChip,
+            _ => Platform::Chip8,
+        }
+    }
+
+    // Size in bytes including any trailing operand word
+    pub fn size(&self) -> u16 {
+        match self {
+            Instruction::LongSetI => 4,
+            _ => 2,
+        }
+    }
+
+    // Whether the instruction conditionally skips the one after it
+    pub fn is_skip(&self) -> bool {
+        matches!(
+            self,
+            Instruction::SkipEqImm { .. }
+                | Instruction::SkipNeImm { .. }
+                | Instruction::SkipEq { .. }
+                | Instruction::SkipNe { .. }
+                | Instruction::SkipKey { .. }
+                | Instruction::SkipNotKey { .. }
+        )
+    }
+}
+
+// Mnemonics follow Cowgod's Chip-8 technical reference, with the usual
+// SUPER-CHIP and XO-CHIP extensions
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Instruction::Nop => write!(f, "NOP"),
+            Instruction::ScrollDown { n } => write!(f, "SCD {}", n),
+            Instruction::ScrollUp { n } => write!(f, "SCU {}", n),
+            Instruction::Cls => write!(f, "CLS"),
+            Instruction::Ret => write!(f, "RET"),
+            Instruction::ScrollRight => write!(f, "SCR"),
+            Instruction::ScrollLeft => write!(f, "SCL"),
+            Instruction::Exit => write!(f, "EXIT"),
+            Instruction::Lores => write!(f, "LOW"),
+            Instruction::Hires => write!(f, "HIGH"),
+            Instruction::Jump { nnn } => write!(f, "JP {:#05x}", nnn),
+            Instruction::Call { nnn } => write!(f, "CALL {:#05x}", nnn),
+            Instruction::SkipEqImm { x, nn } => write!(f, "SE V{:X}, {:#04x}", x, nn),
+            Instruction::SkipNeImm { x, nn } => write!(f, "SNE V{:X}, {:#04x}", x, nn),
+            Instruction::SkipEq { x, y } => write!(f, "SE V{:X}, V{:X}", x, y),
+            Instruction::StoreRange { x, y } => write!(f, "SAVE V{:X} - V{:X}", x, y),
+            Instruction::LoadRange { x, y } => write!(f, "LOAD V{:X} - V{:X}", x, y),
+            Instruction::SetImm { x, nn } => write!(f, "LD V{:X}, {:#04x}", x, nn),
+            Instruction::AddImm { x, nn } => write!(f, "ADD V{:X}, {:#04x}", x, nn),
+            Instruction::Set { x, y } => write!(f, "LD V{:X}, V{:X}", x, y),
+            Instruction::Or { x, y } => write!(f, "OR V{:X}, V{:X}", x, y),
+            Instruction::And { x, y } => write!(f, "AND V{:X}, V{:X}", x, y),
+            Instruction::Xor { x, y } => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Instruction::Add { x, y } => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Instruction::Sub { x, y } => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Instruction::Shr { x, y } => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Instruction::SubN { x, y } => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::Shl { x, y } => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Instruction::SkipNe { x, y } => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Instruction::SetI { nnn } => write!(f, "LD I, {:#05x}", nnn),
+            // V0 is added normally and VX with the jump_vx quirk, X being nnn's top
+            // nibble. Name both registers when they differ.
+            Instruction::JumpOffset { nnn } => match nnn >> 8 {
+                0 => write!(f, "JP V0, {:#05x}", nnn),
+                x => write!(f, "JP V0/V{:X}, {:#05x}", x, nnn),
+            },
+            Instruction::Random { x, nn } => write!(f, "RND V{:X}, {:#04x}", x, nn),
+            Instruction::Draw { x, y, n } => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Instruction::SkipKey { x } => write!(f, "SKP V{:X}", x),
+            Instruction::SkipNotKey { x } => write!(f, "SKNP V{:X}", x),
+            Instruction::LongSetI => write!(f, "LD I, LONG"),
+            Instruction::SelectPlanes { n } => write!(f, "PLANE {}", n),
+            Instruction::LoadAudio => write!(f, "AUDIO"),
+            Instruction::GetDelay { x } => write!(f, "LD V{:X}, DT", x),
+            Instruction::WaitKey { x } => write!(f, "LD V{:X}, K", x),
+            Instruction::SetDelay { x } => write!(f, "LD DT, V{:X}", x),
+            Instruction::SetSound { x } => write!(f, "LD ST, V{:X}", x),
+            Instruction::AddI { x } => write!(f, "ADD I, V{:X}", x),
+            Instruction::Font { x } => write!(f, "LD F, V{:X}", x),
+            Instruction::BigFont { x } => write!(f, "LD HF, V{:X}", x),
+            Instruction::Bcd { x } => write!(f, "LD B, V{:X}", x),
+            Instruction::Pitch { x } => write!(f, "PITCH V{:X}", x),
+            Instruction::Store { x } => write!(f, "LD [I], V{:X}", x),
+            Instruction::Load { x } => write!(f, "LD V{:X}, [I]", x),
+            Instruction::StoreFlags { x } => write!(f, "LD R, V{:X}", x),
+            Instruction::LoadFlags { x } => write!(f, "LD V{:X}, R", x),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_opcode_round_trips() {
+        for op in 0..=u16::MAX {
+            match Instruction::decode(op) {
+                Ok(instruction) => assert_eq!(instruction.encode(), op, "{:04X} decoded as {:?}", op, instruction),
+                Err(err) => assert_eq!(err.op, op),
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_opcodes() {
+        for op in [0x0123, 0x5001, 0x8008, 0x9001, 0xE000, 0xF0FF] {
+            assert!(Instruction::decode(op).is_err(), "{:04X}", op);
+        }
+    }
+
+    #[test]
+    fn platforms() {
+        assert_eq!(Instruction::decode(0x00E0).unwrap().platform(), Platform::Chip8);
+        assert_eq!(Instruction::decode(0xD123).unwrap().platform(), Platform::Chip8);
+        assert_eq!(Instruction::decode(0x00FF).unwrap().platform(), Platform::SuperChip);
+        assert_eq!(Instruction::decode(0xF330).unwrap().platform(), Platform::SuperChip);
+        assert_eq!(Instruction::decode(0x00D4).unwrap().platform(), Platform::XoChip);
+        assert_eq!(Instruction::decode(0xF000).unwrap().platform(), Platform::XoChip);
+        assert_eq!(Instruction::decode(0x5122).unwrap().platform(), Platform::XoChip);
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(Instruction::LongSetI.size(), 4);
+        assert_eq!(Instruction::decode(0x1234).unwrap().size(), 2);
+        assert_eq!(Instruction::decode(0xF002).unwrap().size(), 2);
+    }
+
+    #[test]
+    fn jump_offset_names_both_registers() {
+        assert_eq!(Instruction::decode(0xB345).unwrap().to_string(), "JP V0/V3, 0x345");
+        assert_eq!(Instruction::decode(0xB045).unwrap().to_string(), "JP V0, 0x045");
+    }
+}
@@ -1,8 +1,32 @@
+pub mod assembler;
+pub mod capture;
 pub mod chip8;
 pub mod config;
+pub mod debugger;
+pub mod disassembler;
+pub mod error;
+pub mod filter;
+pub mod frontend;
+pub mod headless;
+pub mod instruction;
 pub mod memory;
+pub mod movie;
+pub mod palette;
+pub mod phosphor;
+pub mod platform;
+pub mod quirks;
 pub mod registers;
+pub mod rewind;
+pub mod romdb;
+pub mod rng;
+pub mod savestate;
+pub mod scheduler;
+pub mod settings;
 pub mod stack;
+pub mod synth;
 pub mod keyboard;
 pub mod screen;
+#[cfg(feature = "sdl")]
+pub mod sdl;
+#[cfg(feature = "audio")]
 pub mod sound;
@@ -1,107 +1,960 @@
-extern crate sdl2;
 use std::io::Read;
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
-use sdl2::pixels::Color;
-use sdl2::rect::Rect;
-use sdl2::render::Canvas;
-use sdl2::video::Window;
 use chip8::config::{
-    CHIP_8_HEIGHT, CHIP_8_WIDTH, CHIP_8_WINDOW_MULTIPLIER, 
-    EMULATOR_WINDOW_TITLE, key2btn, CHIP8_TICKS_PER_FRAME,
+    CHIP_8_DEFAULT_PALETTE, CHIP_8_DEFAULT_TONE_FREQUENCY, CHIP_8_DEFAULT_VOLUME,
+    CHIP_8_DEFAULT_WINDOW_SCALE, CHIP8_PROGRAM_LOAD_ADDRESS, CHIP8_TICKS_PER_FRAME,
+    CHIP8_REWIND_HISTORY_FRAMES,
 };
 use chip8::chip8::*;
+use chip8::platform::Platform;
+use chip8::quirks::{Quirks, QUIRK_NAMES};
+use chip8::disassembler::{disassemble, LineKind};
+use chip8::assembler::assemble;
+use chip8::frontend::{AudioSettings, Waveform};
+use chip8::capture::{Capture, VideoFormat};
+use chip8::headless::{HeadlessOptions, StopReason};
+use chip8::movie::{rom_hash, KeyEvent};
+use chip8::filter::{Filter, FILTER_NAMES};
+use chip8::palette::{overlay, parse_colour, parse_palette, Theme, THEMES};
+use chip8::rng::{Chip8Rng, VipRng};
+use chip8::romdb::{sha1_hex, RomDatabase, RomInfo};
+use chip8::settings::Settings;
+use clap::{Args, CommandFactory, Parser, Subcommand};
 use std::env;
 use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::process;
 
-// Main function: Initializes the Chip8, SDL, and handles the event loop
-pub fn main() -> Result<(), String> {
-    let mut chip8: Chip8 = Chip8::new();
-    load_file(&mut chip8);
-    // Initialize Chip8 system
-    let sdl_context = sdl2::init().unwrap();
-    let video_subsystem = sdl_context.video().unwrap();
-    let window = video_subsystem
-        .window(EMULATOR_WINDOW_TITLE, (CHIP_8_WIDTH * CHIP_8_WINDOW_MULTIPLIER) as u32, (CHIP_8_HEIGHT * CHIP_8_WINDOW_MULTIPLIER) as u32)
-        .position_centered()
-        .opengl()
-        .build()
-        .unwrap();
-
-    let mut canvas = window.into_canvas().present_vsync().build().unwrap();
-    canvas.clear();
-    canvas.present();
-    
-    let mut event_pump = sdl_context.event_pump().unwrap();
+// Only the windowed emulator needs SDL, the other subcommands work without it
+#[cfg(feature = "sdl")]
+use chip8::config::{
+    CHIP_8_HEIGHT, CHIP_8_WIDTH, EMULATOR_WINDOW_TITLE,
+    CHIP8_DEFAULT_INSTRUCTIONS_PER_SECOND, CHIP_8_TIMER_HZ, CHIP_8_DEFAULT_PHOSPHOR_DECAY,
+};
+#[cfg(feature = "sdl")]
+use chip8::frontend::{Display, Input, InputEvent};
+#[cfg(feature = "sdl")]
+use chip8::capture::create_timestamped;
+#[cfg(all(feature = "sdl", feature = "audio"))]
+use chip8::sound::RodioAudio;
+#[cfg(feature = "sdl")]
+use chip8::error::Chip8Error;
+#[cfg(feature = "sdl")]
+use chip8::debugger::Debugger;
+#[cfg(feature = "sdl")]
+use chip8::rewind::Rewind;
+#[cfg(feature = "sdl")]
+use chip8::scheduler::Scheduler;
+#[cfg(feature = "sdl")]
+use chip8::sdl::Keymap;
+#[cfg(feature = "sdl")]
+use chip8::phosphor::Phosphor;
+#[cfg(feature = "sdl")]
+use chip8::filter;
+#[cfg(feature = "sdl")]
+use std::time::Instant;
+#[cfg(feature = "sdl")]
+use chip8::movie::{Movie, MoviePlayer, MovieRecorder};
+#[cfg(feature = "sdl")]
+use std::io::BufRead;
+#[cfg(feature = "sdl")]
+use std::sync::mpsc;
+#[cfg(feature = "sdl")]
+use std::thread;
+
+/// A CHIP-8, SUPER-CHIP and XO-CHIP emulator
+#[derive(Parser)]
+#[command(name = "chip-8", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Play a ROM in a window (the default when given a file name)
+    Run(RunArgs),
+    /// Start a ROM paused, with debugger commands read from stdin
+    Debug(RunArgs),
+    /// Print a listing of a ROM
+    Disasm {
+        rom: String,
+        /// Platform whose opcodes are decoded, guessed from the file extension by default
+        #[arg(long, value_parser = parse_platform)]
+        platform: Option<Platform>,
+    },
+    /// Assemble Octo source into a ROM and print the symbol table
+    Assemble {
+        source: String,
+        out: String,
+    },
+    /// Print the size, platform and contents of a ROM, and what the ROM database knows about it
+    Info {
+        rom: String,
+        #[command(flatten)]
+        config: ConfigArgs,
+    },
+    /// Run a ROM without a window and print the final screen and its hash
+    Headless(HeadlessArgs),
+}
+
+// The ROM and how to set up the interpreter for it, shared by the subcommands that run one
+#[derive(Args)]
+struct MachineArgs {
+    /// ROM file to load
+    rom: String,
+    /// Platform to emulate: chip8, schip or xochip. Guessed from the file extension by default
+    #[arg(long, value_parser = parse_platform)]
+    platform: Option<Platform>,
+    /// Quirks preset: vip, schip or xochip. The platform's own by default
+    #[arg(long, value_parser = parse_quirks)]
+    quirks: Option<Quirks>,
+    /// Turn a single quirk on or off, e.g. --quirk shift=1
+    #[arg(long = "quirk", value_name = "NAME=0|1", value_parser = parse_quirk)]
+    quirk_overrides: Vec<(String, bool)>,
+    /// Seed for CXNN's random numbers, decimal or 0x prefixed hex
+    #[arg(long, value_parser = parse_seed)]
+    seed: Option<u64>,
+    /// Generate random numbers with the COSMAC VIP's routine
+    #[arg(long)]
+    vip_random: bool,
+    #[command(flatten)]
+    config: ConfigArgs,
+}
+
+// Where per-ROM settings are looked up. Options given on the command line win over
+// the settings file, which wins over the ROM database.
+#[derive(Args)]
+struct ConfigArgs {
+    /// Settings file with per-ROM overrides [default: ~/.config/chip-8/config.toml]
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+    /// ROM database, the community CHIP-8 database's programs.json [default: programs.json next to the settings file]
+    #[arg(long, value_name = "FILE")]
+    database: Option<PathBuf>,
+    /// Ignore the settings file and ROM database
+    #[arg(long, conflicts_with_all = ["config", "database"])]
+    no_config: bool,
+}
+
+// The window, sound and input options aren't read when built without SDL
+#[derive(Args)]
+#[cfg_attr(not(feature = "sdl"), allow(dead_code))]
+struct RunArgs {
+    #[command(flatten)]
+    machine: MachineArgs,
+    /// Starting window size as a multiple of the 64x32 display, the window can be resized
+    #[arg(long, default_value_t = CHIP_8_DEFAULT_WINDOW_SCALE, value_parser = parse_scale)]
+    scale: usize,
+    /// Start fullscreen, F11 or alt+enter switches back to a window
+    #[arg(long)]
+    fullscreen: bool,
+    /// CPU speed in instructions per second [default: the ROM's tickrate, or 600]
+    #[arg(long, visible_alias = "ips", value_parser = parse_speed)]
+    speed: Option<u32>,
+    /// Run the same number of instructions every frame instead of keeping to real time
+    #[arg(long)]
+    frame_locked: bool,
+    /// Frames of history kept for rewinding with backspace, 0 turns rewinding off
+    #[arg(long, value_name = "FRAMES", default_value_t = CHIP8_REWIND_HISTORY_FRAMES)]
+    rewind_frames: usize,
+    #[command(flatten)]
+    colours: PaletteArgs,
+    /// Fade pixels out over a few frames to hide flicker. DECAY is the share of
+    /// brightness lost each frame, from 0 to 1 [default: 0.4]
+    #[arg(long, value_name = "DECAY", num_args = 0..=1, value_parser = parse_decay)]
+    phosphor: Option<Option<f32>>,
+    /// Post-processing filters, run in order: scale2x, scale3x, xbr, scanlines, grid or crt
+    #[arg(long = "filter", value_name = "FILTER", value_delimiter = ',', value_parser = parse_filter)]
+    filters: Vec<Filter>,
+    /// Buzzer frequency in Hz
+    #[arg(long, default_value_t = CHIP_8_DEFAULT_TONE_FREQUENCY, value_parser = parse_frequency)]
+    tone: f32,
+    /// Buzzer waveform: square, sine or triangle
+    #[arg(long, default_value = "square", value_parser = parse_waveform)]
+    waveform: Waveform,
+    /// Buzzer volume from 0 to 1
+    #[arg(long, default_value_t = CHIP_8_DEFAULT_VOLUME, value_parser = parse_volume)]
+    volume: f32,
+    /// Key map file of `host key = chip-8 key` lines
+    #[arg(long, value_name = "FILE")]
+    keymap: Option<String>,
+    /// Record the session's input to a movie file on exit
+    #[arg(long, value_name = "MOVIE", conflicts_with = "play")]
+    record: Option<String>,
+    /// Replay a movie file
+    #[arg(long, value_name = "MOVIE")]
+    play: Option<String>,
+}
+
+// Colours of the window, screenshots and recordings. These go over the ROM's own
+// colours from the settings file or ROM database, which go over the settings file's theme.
+#[derive(Args)]
+#[cfg_attr(not(feature = "sdl"), allow(dead_code))]
+struct PaletteArgs {
+    /// Colour theme: classic, green, amber, lcd, high-contrast or colour-blind
+    #[arg(long, value_parser = parse_theme)]
+    theme: Option<Theme>,
+    /// Up to four comma separated RRGGBB colours: background, plane 1, plane 2 and both planes
+    #[arg(long, value_name = "COLOURS", value_parser = parse_palette_arg)]
+    palette: Option<Colours>,
+    /// Colour of lit pixels, as RRGGBB hex
+    #[arg(long, value_name = "RRGGBB", value_parser = parse_colour_arg)]
+    foreground: Option<[u8; 3]>,
+    /// Colour of unlit pixels, as RRGGBB hex
+    #[arg(long, value_name = "RRGGBB", value_parser = parse_colour_arg)]
+    background: Option<[u8; 3]>,
+}
+
+// A list of colours, kept in one value so clap doesn't take it for repeated options
+#[derive(Clone)]
+struct Colours(Vec<[u8; 3]>);
+
+impl PaletteArgs {
+    fn apply(&self, palette: &mut [[u8; 3]; 4]) {
+        if let Some(theme) = self.theme {
+            *palette = theme.colours;
+        }
+        if let Some(Colours(colours)) = &self.palette {
+            overlay(palette, colours);
+        }
+        if let Some(background) = self.background {
+            palette[0] = background;
+        }
+        if let Some(foreground) = self.foreground {
+            palette[1] = foreground;
+        }
+    }
+}
+
+// CXNN uses seed 0 unless told otherwise so headless runs are repeatable
+#[derive(Args)]
+struct HeadlessArgs {
+    #[command(flatten)]
+    machine: MachineArgs,
+    /// Number of frames to run
+    #[arg(long, default_value_t = 600)]
+    frames: u32,
+    /// Stop before executing the instruction at this hex address
+    #[arg(long, value_name = "ADDR", value_parser = parse_hex_u16)]
+    until_pc: Option<u16>,
+    /// Stop before executing this hex opcode
+    #[arg(long, value_name = "OPCODE", value_parser = parse_hex_u16)]
+    until_opcode: Option<u16>,
+    /// Press a hex key at a frame and hold it, for 6 frames by default
+    #[arg(long, value_name = "FRAME:KEY[:FRAMES]", value_parser = parse_press)]
+    press: Vec<[KeyEvent; 2]>,
+    /// Write the final screen to a PNG file, or stdout for -
+    #[arg(long, value_name = "FILE")]
+    png: Option<String>,
+    #[command(flatten)]
+    colours: PaletteArgs,
+    /// Post-processing filters for the PNG and video, run in order: scale2x, scale3x,
+    /// xbr, scanlines, grid or crt
+    #[arg(long = "filter", value_name = "FILTER", value_delimiter = ',', value_parser = parse_filter)]
+    filters: Vec<Filter>,
+    /// Pixel size of the PNG, and of high resolution pixels in the video, after filtering
+    #[arg(long, default_value_t = 1, value_parser = parse_scale)]
+    scale: usize,
+    /// Record every frame to a .gif, .y4m or .ppm video
+    #[arg(long, value_name = "FILE")]
+    video: Option<PathBuf>,
+    /// Record the sound to a WAV file
+    #[arg(long, value_name = "FILE")]
+    wav: Option<PathBuf>,
+}
+
+pub fn main() {
+    // `chip-8 <rom>` is short for `chip-8 run <rom>`
+    let mut args: Vec<String> = env::args().collect();
+    if let Some(first) = args.get(1) {
+        if !first.starts_with('-') && Cli::command().find_subcommand(first).is_none() {
+            args.insert(1, "run".to_string());
+        }
+    }
+
+    let result = match Cli::parse_from(args).command {
+        Command::Run(args) => run(args, false),
+        Command::Debug(args) => run(args, true),
+        Command::Disasm { rom, platform } => disasm(&rom, platform),
+        Command::Assemble { source, out } => assemble_file(&source, &out),
+        Command::Info { rom, config } => info(&rom, &config),
+        Command::Headless(args) => run_headless(args),
+    };
+    if let Err(err) = result {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    }
+}
+
+#[cfg(not(feature = "sdl"))]
+fn run(_args: RunArgs, _debug: bool) -> Result<(), String> {
+    Err("built without the sdl feature, only disasm, assemble, info and headless are available".to_string())
+}
+
+// Initializes the Chip8, SDL, and handles the event loop
+#[cfg(feature = "sdl")]
+fn run(args: RunArgs, debug: bool) -> Result<(), String> {
+    let rom_name = &args.machine.rom;
+    let (guessed, rom) = load_file(rom_name)?;
+    let (info, mut palette) = rom_info(&args.machine.config, &rom)?;
+    let movie = match &args.play {
+        Some(path) => Some(load_movie(path, &rom)?),
+        None => None,
+    };
+    // A movie is only reproducible with a known seed, so pick one up front
+    let seed = args.machine.seed.unwrap_or_else(rand::random);
+    let platform = match &movie {
+        Some(movie) => movie.platform,
+        None => select_platform(&args.machine, &info, guessed),
+    };
+    let mut chip8 = new_machine(&args.machine, &info, platform, &rom, seed)?;
+    if let Some(movie) = &movie {
+        movie.configure(&mut chip8);
+    }
+    let mut player = movie.map(MoviePlayer::new);
+    let mut recorder = args.record.as_ref().map(|_| {
+        MovieRecorder::new(Movie::new(&rom, seed, args.machine.vip_random, platform, chip8.quirks))
+    });
+    let mut keymap = match &args.keymap {
+        Some(path) => {
+            let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+            Keymap::parse(&text).map_err(|e| format!("{}: {}", path, e))?
+        },
+        None => Keymap::default(),
+    };
+    keymap.add_hints(&info.keys);
+    if !info.keys.is_empty() {
+        let hints: Vec<String> = info.keys.iter().map(|(name, key)| format!("{} = {:X}", name, key)).collect();
+        println!("Keys: {}", hints.join(", "));
+    }
+    let speed = args.speed
+        .or(info.tickrate.map(|tickrate| tickrate * CHIP_8_TIMER_HZ))
+        .unwrap_or(CHIP8_DEFAULT_INSTRUCTIONS_PER_SECOND);
+    let window_title = match &info.title {
+        Some(title) => format!("{} - {}", EMULATOR_WINDOW_TITLE, title),
+        None => EMULATOR_WINDOW_TITLE.to_string(),
+    };
+
+    let mut debugger = Debugger::new();
+    let commands = if debug {
+        debugger.pause();
+        print!("{}", Debugger::describe(&chip8, chip8.registers.pc));
+        Some(spawn_command_reader())
+    } else {
+        None
+    };
+
+    // Initialize the frontend
+    let (mut display, mut input) = chip8::sdl::init(
+        &window_title,
+        (CHIP_8_WIDTH * args.scale) as u32,
+        (CHIP_8_HEIGHT * args.scale) as u32,
+        args.fullscreen)?;
+    input.keymap = keymap;
+    args.colours.apply(&mut palette);
+    display.palette = palette;
+    let decay = args.phosphor.flatten().unwrap_or(CHIP_8_DEFAULT_PHOSPHOR_DECAY);
+    if args.phosphor.is_some() {
+        display.phosphor = Some(Phosphor::new(decay));
+    }
+    display.filters = args.filters.clone();
+    // Scaled screenshots come out near the window's size, whatever the filters already did
+    let screenshot_scale = (args.scale / filter::scale(&args.filters)).max(1);
+    let audio_settings = AudioSettings { frequency: args.tone, waveform: args.waveform, volume: args.volume };
+    #[cfg(feature = "audio")]
+    match RodioAudio::new(audio_settings) {
+        Ok(audio) => chip8.audio = Box::new(audio),
+        Err(err) => eprintln!("No audio: {}", err),
+    }
+    // F10 records a clip of what's shown and heard
+    let mut capture: Option<Capture> = None;
+
+    // Set once the interpreter faults; the window stays open showing the last frame
+    let mut crashed: Option<Chip8Error> = None;
+    // Holding backspace plays the last few seconds backwards
+    let mut rewind = Rewind::new(args.rewind_frames);
+    rewind.record(&chip8);
+    let mut rewinding = false;
+    // Movies count frames, so they need a fixed number of instructions per frame
+    let mut scheduler = Scheduler::new(speed, args.frame_locked || recorder.is_some() || player.is_some());
 
     'gameloop: loop {
-        for evt in event_pump.poll_iter() {
-            match evt {
-                Event::Quit{..} | Event::KeyDown{keycode: Some(Keycode::Escape), ..}=> {
-                    break 'gameloop;
+        // Jumping around in time would make a movie unplayable
+        let movie_active = recorder.is_some() || player.is_some();
+        for event in input.poll() {
+            match event {
+                InputEvent::Quit => break 'gameloop,
+                InputEvent::SaveState(slot) if !movie_active => {
+                    match save_state(&chip8, rom_name, slot) {
+                        Ok(()) => println!("Saved state to slot {}", slot),
+                        Err(err) => eprintln!("Couldn't save slot {}: {}", slot, err),
+                    }
                 },
-                Event::KeyDown{keycode: Some(key), ..} => {
-                    if let Some(k) = key2btn(key) {
-                        chip8.chip8_keyboard.chip8_keyboard_down(k as u8);
+                InputEvent::LoadState(slot) if !movie_active => {
+                    match load_state(&mut chip8, rom_name, slot) {
+                        Ok(()) => {
+                            println!("Loaded state from slot {}", slot);
+                            // Loading a state from before a crash resumes the game
+                            if crashed.take().is_some() {
+                                display.set_title(&window_title)?;
+                            }
+                        },
+                        Err(err) => eprintln!("Couldn't load slot {}: {}", slot, err),
                     }
                 },
-                Event::KeyUp{keycode: Some(key), ..} => {
-                    if let Some(k) = key2btn(key) {
-                        chip8.chip8_keyboard.chip8_keyboard_up(k as u8);
+                InputEvent::Screenshot { scaled } => {
+                    let scale = if scaled { screenshot_scale } else { 1 };
+                    match chip8.get_display().save_screenshot(Path::new(rom_name), &display.palette, &display.filters, scale) {
+                        Ok(path) => println!("Saved screenshot to {}", path.display()),
+                        Err(err) => eprintln!("Couldn't save screenshot: {}", err),
                     }
                 },
-                _ => ()
+                InputEvent::NextTheme => {
+                    let theme = Theme::next(&display.palette);
+                    display.palette = theme.colours;
+                    println!("Theme: {}", theme.name);
+                },
+                InputEvent::ToggleFullscreen => {
+                    if let Err(err) = display.toggle_fullscreen() {
+                        eprintln!("Couldn't switch fullscreen: {}", err);
+                    }
+                },
+                InputEvent::TogglePhosphor => {
+                    display.phosphor = match display.phosphor {
+                        Some(_) => None,
+                        None => Some(Phosphor::new(decay)),
+                    };
+                    println!("Phosphor {}", if display.phosphor.is_some() { "on" } else { "off" });
+                },
+                InputEvent::ToggleCapture => match capture.take() {
+                    Some(clip) => finish_capture(clip),
+                    None => match start_capture(rom_name, display.palette, &display.filters, args.scale, audio_settings) {
+                        Ok((clip, path)) => {
+                            println!("Recording clip to {}", path.display());
+                            capture = Some(clip);
+                        },
+                        Err(err) => eprintln!("Couldn't start recording: {}", err),
+                    },
+                },
+                InputEvent::TogglePause => {
+                    if debugger.is_paused() {
+                        debugger.unpause(&chip8);
+                    } else {
+                        debugger.pause();
+                    }
+                },
+                // While paused rewinding steps back a single frame
+                InputEvent::Rewind(true) if !movie_active => {
+                    if !debugger.is_paused() {
+                        rewinding = true;
+                    } else if step_back(&mut chip8, &mut rewind) {
+                        println!("Stepped back a frame, {} left", rewind.len());
+                    }
+                },
+                InputEvent::Rewind(false) => rewinding = false,
+                // The movie provides the input while it plays
+                InputEvent::KeyDown(key) if player.is_none() => chip8.chip8_keyboard.chip8_keyboard_down(key),
+                InputEvent::KeyUp(key) if player.is_none() => chip8.chip8_keyboard.chip8_keyboard_up(key),
+                _ => (),
             }
         }
 
-        for _ in 0..CHIP8_TICKS_PER_FRAME {
-            chip8.tick();
+        if let Some(commands) = &commands {
+            while let Ok(line) = commands.try_recv() {
+                print!("{}", debugger.command(&mut chip8, &line));
+            }
         }
-        chip8.tick_timers();
-        draw_screen(&chip8, &mut canvas).unwrap();
+
+        if rewinding {
+            // Rewinding out of a crash lets the game carry on from an earlier frame
+            if step_back(&mut chip8, &mut rewind) && crashed.take().is_some() {
+                display.set_title(&window_title)?;
+            }
+        } else if crashed.is_none() && !debugger.is_paused() {
+            for (instructions, timer_tick) in scheduler.advance(Instant::now()).batches() {
+                if crashed.is_some() || debugger.is_paused() {
+                    break;
+                }
+                // A batch followed by a timer tick is a whole frame, which is what movies count
+                if timer_tick {
+                    if let Some(recorder) = recorder.as_mut() {
+                        recorder.record_frame(&chip8);
+                    }
+                    if let Some(movie) = player.as_mut() {
+                        if let Err(desync) = movie.play_frame(&mut chip8) {
+                            eprintln!("Movie {}", desync);
+                        }
+                        if movie.is_finished() {
+                            println!("Movie finished after {} frames", movie.frame());
+                            player = None;
+                        }
+                    }
+                }
+                match debugger.run(&mut chip8, instructions) {
+                    Ok(Some(message)) => print!("{}", message),
+                    Ok(None) => (),
+                    // When debugging, stop on the faulting instruction so it can be inspected
+                    Err(err) if debug => {
+                        println!("error: {}", err);
+                        debugger.pause();
+                    },
+                    Err(err) => {
+                        eprintln!("Emulator crashed: {}", err);
+                        let title = format!("{} - crashed: {}", window_title, err);
+                        display.set_title(&title)?;
+                        crashed = Some(err);
+                    },
+                }
+                if timer_tick && !debugger.is_paused() && crashed.is_none() {
+                    if let Some(clip) = capture.as_mut() {
+                        if let Err(err) = clip.frame(&chip8) {
+                            eprintln!("Recording stopped: {}", err);
+                            capture = None;
+                        }
+                    }
+                    chip8.tick_timers();
+                    rewind.record(&chip8);
+                }
+            }
+        }
+        // Time spent paused, rewinding or crashed doesn't count
+        if rewinding || crashed.is_some() || debugger.is_paused() {
+            scheduler.reset();
+        }
+        display.present(chip8.get_display())?;
     }
 
+    if let Some(clip) = capture {
+        finish_capture(clip);
+    }
+    if let (Some(path), Some(recorder)) = (&args.record, recorder) {
+        let movie = recorder.finish();
+        std::fs::write(path, movie.to_string()).map_err(|e| format!("{}: {}", path, e))?;
+        println!("Recorded {} frames to {}", movie.frames, path);
+    }
     Ok(())
 }
 
-// file handle
-fn load_file(chip8: &mut Chip8) {
-    // Collect command-line arguments
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        panic!("You must provide a file to load");
+// The platform given on the command line, else the one the ROM is known to need,
+// else the one its file extension suggests
+fn select_platform(args: &MachineArgs, info: &RomInfo, guessed: Platform) -> Platform {
+    args.platform.or(info.platform).unwrap_or(guessed)
+}
+
+// Create an interpreter for the platform, load the ROM and apply the quirk and random options
+fn new_machine(args: &MachineArgs, info: &RomInfo, platform: Platform, rom: &[u8], seed: u64) -> Result<Chip8, String> {
+    let mut chip8 = Chip8::with_platform(platform);
+    chip8.chip8_load(rom, rom.len()).map_err(|e| format!("{}: {}", args.rom, e))?;
+    chip8.quirks = match args.quirks {
+        Some(quirks) => quirks,
+        None => info.resolve_quirks(platform),
+    };
+    for (name, value) in &args.quirk_overrides {
+        chip8.quirks.set(name, *value);
     }
+    chip8.rng = if args.vip_random {
+        Box::new(VipRng::from_seed(seed as u16))
+    } else {
+        Box::new(Chip8Rng::from_seed(seed))
+    };
+    Ok(chip8)
+}
 
-    // Clone the file name from the arguments
-    let file_name = args[1].clone();
+// Settings are kept in $XDG_CONFIG_HOME/chip-8, or ~/.config/chip-8
+fn config_dir() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("chip-8"))
+}
 
-    // Open the file in read-binary mode
-    let mut file = File::open(&file_name).unwrap();
+// What the ROM database and the settings file say about a ROM, and the colours they
+// give it. Files named on the command line have to exist, the default ones are
+// skipped when they don't.
+fn rom_info(args: &ConfigArgs, rom: &[u8]) -> Result<(RomInfo, [[u8; 3]; 4]), String> {
+    if args.no_config {
+        return Ok((RomInfo::default(), CHIP_8_DEFAULT_PALETTE));
+    }
+    let dir = config_dir();
+    let settings_path = args.config.clone().or_else(|| dir.as_ref().map(|dir| dir.join("config.toml")));
+    let settings = match &settings_path {
+        Some(path) if args.config.is_some() || path.exists() => {
+            let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            Settings::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))?
+        },
+        _ => Settings::default(),
+    };
 
-    // Read the file contents into a Vec<u8>
-    let mut buffer: Vec<u8> = Vec::new();
-    file.read_to_end(&mut buffer).unwrap();
-    chip8.chip8_load(&buffer, buffer.len());
-}
-
-fn draw_screen(chip8: &Chip8, canvas: &mut Canvas<Window>) -> Result<(), String>{
-    // Clear canvas as black
-    canvas.set_draw_color(Color::RGB(0, 0, 0));
-    canvas.clear();
-
-    // Now set draw color to white, iterate through each point and see if it should be drawn
-    canvas.set_draw_color(Color::RGB(255, 255, 255));
-    for x in 0..CHIP_8_WIDTH {
-        for y in 0..CHIP_8_HEIGHT {
-            if chip8.chip8_screen.is_set_screen(x, y) {
-                canvas.fill_rect(Rect::new(
-                    (x * CHIP_8_WINDOW_MULTIPLIER) as i32, 
-                    (y * CHIP_8_WINDOW_MULTIPLIER) as i32, 
-                    CHIP_8_WINDOW_MULTIPLIER as u32, 
-                    CHIP_8_WINDOW_MULTIPLIER as u32))?;
+    // A database named in the settings file is relative to that file
+    let named = args.database.clone().or_else(|| {
+        let database = settings.database.as_ref()?;
+        let base = settings_path.as_ref().and_then(|path| path.parent()).unwrap_or(Path::new(""));
+        Some(base.join(database))
+    });
+    let database_path = named.clone().or_else(|| dir.map(|dir| dir.join("programs.json")));
+    let database = match &database_path {
+        Some(path) if named.is_some() || path.exists() => {
+            let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            RomDatabase::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))?
+        },
+        _ => RomDatabase::new(),
+    };
+
+    let hash = sha1_hex(rom);
+    let mut info = database.get(&hash).cloned().unwrap_or_default();
+    if let Some(overrides) = settings.rom(&hash) {
+        info.merge(overrides);
+    }
+    let mut palette = settings.palette;
+    info.apply_palette(&mut palette);
+    Ok((info, palette))
+}
+
+// Read a movie and check it was recorded with this ROM
+#[cfg(feature = "sdl")]
+fn load_movie(path: &str, rom: &[u8]) -> Result<Movie, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let movie = Movie::parse(&text).map_err(|e| format!("{}: {}", path, e))?;
+    if movie.rom_hash != rom_hash(rom) {
+        return Err(format!("{}: recorded with a different ROM", path));
+    }
+    Ok(movie)
+}
+
+fn parse_platform(text: &str) -> Result<Platform, String> {
+    Platform::from_name(text).ok_or(format!("unknown platform '{}', expected chip8, schip or xochip", text))
+}
+
+fn parse_quirks(text: &str) -> Result<Quirks, String> {
+    Quirks::preset(text).ok_or(format!("unknown quirks preset '{}', expected vip, schip or xochip", text))
+}
+
+// `name=0` or `name=1`
+fn parse_quirk(text: &str) -> Result<(String, bool), String> {
+    let (name, value) = text.split_once('=').ok_or("expected NAME=0 or NAME=1")?;
+    if Quirks::cosmac_vip().get(name).is_none() {
+        return Err(format!("unknown quirk '{}'", name));
+    }
+    match value {
+        "0" => Ok((name.to_string(), false)),
+        "1" => Ok((name.to_string(), true)),
+        _ => Err(format!("invalid quirk value '{}', expected 0 or 1", value)),
+    }
+}
+
+// Seeds are decimal or 0x prefixed hex
+fn parse_seed(text: &str) -> Result<u64, String> {
+    let parsed = match text.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    parsed.map_err(|_| format!("invalid seed '{}'", text))
+}
+
+fn parse_scale(text: &str) -> Result<usize, String> {
+    text.parse().ok().filter(|&scale| scale > 0).ok_or(format!("invalid scale '{}'", text))
+}
+
+fn parse_speed(text: &str) -> Result<u32, String> {
+    text.parse().ok().filter(|&ips| ips > 0).ok_or(format!("invalid instructions per second '{}'", text))
+}
+
+fn parse_colour_arg(text: &str) -> Result<[u8; 3], String> {
+    parse_colour(text).ok_or(format!("invalid colour '{}', expected RRGGBB", text))
+}
+
+fn parse_palette_arg(text: &str) -> Result<Colours, String> {
+    parse_palette(text).map(Colours).ok_or(format!("invalid palette '{}', expected up to four RRGGBB colours", text))
+}
+
+fn parse_theme(text: &str) -> Result<Theme, String> {
+    let names: Vec<&str> = THEMES.iter().map(|theme| theme.name).collect();
+    Theme::from_name(text).ok_or(format!("unknown theme '{}', expected one of {}", text, names.join(", ")))
+}
+
+fn parse_frequency(text: &str) -> Result<f32, String> {
+    text.parse().ok().filter(|&hz: &f32| hz > 0.0).ok_or(format!("invalid tone frequency '{}'", text))
+}
+
+fn parse_waveform(text: &str) -> Result<Waveform, String> {
+    Waveform::from_name(text).ok_or(format!("unknown waveform '{}', expected square, sine or triangle", text))
+}
+
+fn parse_filter(text: &str) -> Result<Filter, String> {
+    Filter::from_name(text).ok_or(format!("unknown filter '{}', expected one of {}", text, FILTER_NAMES.join(", ")))
+}
+
+fn parse_decay(text: &str) -> Result<f32, String> {
+    text.parse().ok().filter(|decay| (0.0..=1.0).contains(decay)).ok_or(format!("invalid decay '{}', expected 0 to 1", text))
+}
+
+fn parse_volume(text: &str) -> Result<f32, String> {
+    text.parse().ok().filter(|volume| (0.0..=1.0).contains(volume)).ok_or(format!("invalid volume '{}'", text))
+}
+
+// Go back one snapshot, keeping the keys that are physically held down
+#[cfg(feature = "sdl")]
+fn step_back(chip8: &mut Chip8, rewind: &mut Rewind) -> bool {
+    let keyboard = chip8.chip8_keyboard.keyboard;
+    let rewound = rewind.rewind(chip8).unwrap_or_else(|err| {
+        eprintln!("Couldn't rewind: {}", err);
+        false
+    });
+    chip8.chip8_keyboard.keyboard = keyboard;
+    rewound
+}
+
+// Save states are kept next to the ROM, e.g. pong.ch8.state1
+#[cfg(feature = "sdl")]
+fn state_path(rom_name: &str, slot: u8) -> String {
+    format!("{}.state{}", rom_name, slot)
+}
+
+#[cfg(feature = "sdl")]
+fn save_state(chip8: &Chip8, rom_name: &str, slot: u8) -> Result<(), String> {
+    let path = state_path(rom_name, slot);
+    std::fs::write(&path, chip8.save_state()).map_err(|e| format!("{}: {}", path, e))
+}
+
+#[cfg(feature = "sdl")]
+fn load_state(chip8: &mut Chip8, rom_name: &str, slot: u8) -> Result<(), String> {
+    let path = state_path(rom_name, slot);
+    let data = std::fs::read(&path).map_err(|e| format!("{}: {}", path, e))?;
+    chip8.load_state(&data).map_err(|e| format!("{}: {}", path, e))
+}
+
+// Record a GIF and a WAV beside the ROM, named like screenshots
+#[cfg(feature = "sdl")]
+fn start_capture(rom_name: &str, palette: [[u8; 3]; 4], filters: &[Filter], scale: usize, audio: AudioSettings) -> std::io::Result<(Capture, PathBuf)> {
+    let (video, path) = create_timestamped(Path::new(rom_name), VideoFormat::Gif.extension())?;
+    let wav = File::create(path.with_extension("wav"))?;
+    // Clips are sized for high resolution, which has twice the pixels of the window's
+    // scale, and the filters make them bigger still
+    let scale = (scale / 2 / filter::scale(filters)).max(1);
+    let capture = Capture::new(Some((video, VideoFormat::Gif)), Some(wav), palette, filters.to_vec(), scale, audio)?;
+    Ok((capture, path))
+}
+
+#[cfg(feature = "sdl")]
+fn finish_capture(capture: Capture) {
+    let frames = capture.frames();
+    match capture.finish() {
+        Ok(()) => println!("Recorded a clip of {} frames", frames),
+        Err(err) => eprintln!("Couldn't finish recording: {}", err),
+    }
+}
+
+// Read debugger commands from stdin on a separate thread so the window keeps rendering
+#[cfg(feature = "sdl")]
+fn spawn_command_reader() -> mpsc::Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let stdin = std::io::stdin();
+        for line in stdin.lock().lines().map_while(Result::ok) {
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+fn run_headless(args: HeadlessArgs) -> Result<(), String> {
+    let rom_name = &args.machine.rom;
+    let (guessed, rom) = load_file(rom_name)?;
+    let (info, mut palette) = rom_info(&args.machine.config, &rom)?;
+    let platform = select_platform(&args.machine, &info, guessed);
+    let mut chip8 = new_machine(&args.machine, &info, platform, &rom, args.machine.seed.unwrap_or(0))?;
+    args.colours.apply(&mut palette);
+
+    let options = HeadlessOptions {
+        frames: args.frames,
+        ticks_per_frame: info.tickrate.map_or(CHIP8_TICKS_PER_FRAME, |tickrate| tickrate as usize),
+        until_pc: args.until_pc,
+        until_opcode: args.until_opcode,
+        keys: args.press.into_iter().flatten().collect(),
+    };
+    let video = match &args.video {
+        Some(path) => {
+            let format = VideoFormat::from_path(path)
+                .ok_or(format!("{}: unknown video format, expected .gif, .y4m or .ppm", path.display()))?;
+            Some((File::create(path).map_err(|e| format!("{}: {}", path.display(), e))?, format))
+        },
+        None => None,
+    };
+    let wav = match &args.wav {
+        Some(path) => Some(File::create(path).map_err(|e| format!("{}: {}", path.display(), e))?),
+        None => None,
+    };
+    let mut capture = if video.is_some() || wav.is_some() {
+        Some(Capture::new(video, wav, palette, args.filters.clone(), args.scale, AudioSettings::default()).map_err(|e| format!("recording: {}", e))?)
+    } else {
+        None
+    };
+    let mut capture_error = None;
+    let report = chip8::headless::run_with(&mut chip8, &options, |chip8| {
+        if let Some(clip) = capture.as_mut() {
+            if let Err(err) = clip.frame(chip8) {
+                capture_error.get_or_insert(err);
             }
         }
+    });
+    let report = report.map_err(|err| format!("{}: {}", rom_name, err))?;
+    if let Some(err) = capture_error {
+        return Err(format!("recording: {}", err));
+    }
+    if let Some(clip) = capture {
+        clip.finish().map_err(|e| format!("recording: {}", e))?;
+    }
+    let screen = chip8.get_display();
+    let summary = format!("frames {}\nstopped {}\nhash {:08x}", report.frames, describe_stop(report.reason), screen.hash());
+
+    match args.png.as_deref() {
+        // PNG data goes to stdout, so the summary goes to stderr
+        Some("-") => {
+            screen.write_png(std::io::stdout().lock(), &palette, &args.filters, args.scale).map_err(|e| e.to_string())?;
+            eprintln!("{}", summary);
+        },
+        Some(path) => {
+            let file = File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+            screen.write_png(std::io::BufWriter::new(file), &palette, &args.filters, args.scale)
+                .map_err(|e| format!("{}: {}", path, e))?;
+            println!("{}", summary);
+        },
+        None => {
+            print!("{}", screen.to_ascii());
+            println!("{}", summary);
+        },
     }
-    canvas.present();
     Ok(())
-}
\ No newline at end of file
+}
+
+fn describe_stop(reason: StopReason) -> String {
+    match reason {
+        StopReason::Frames => "after the frame limit".to_string(),
+        StopReason::Pc(pc) => format!("at pc {:03X}", pc),
+        StopReason::Opcode { pc, op } => format!("at opcode {:04X} at {:03X}", op, pc),
+        StopReason::Exited => "by exit".to_string(),
+    }
+}
+
+fn parse_hex_u16(text: &str) -> Result<u16, String> {
+    let digits = text.strip_prefix("0x").unwrap_or(text);
+    u16::from_str_radix(digits, 16).map_err(|_| format!("invalid hex value '{}'", text))
+}
+
+// `frame:key[:frames]` presses a hex key at a frame and holds it, for 6 frames by default
+fn parse_press(text: &str) -> Result<[KeyEvent; 2], String> {
+    let invalid = || format!("invalid key press '{}', expected frame:key[:frames]", text);
+    let parts: Vec<&str> = text.split(':').collect();
+    let (frame, key, held) = match parts.as_slice() {
+        [frame, key] => (frame, key, "6"),
+        [frame, key, held] => (frame, key, *held),
+        _ => return Err(invalid()),
+    };
+    let frame: u32 = frame.parse().map_err(|_| invalid())?;
+    let key = u8::from_str_radix(key, 16).ok().filter(|&key| key < 16).ok_or_else(invalid)?;
+    let held: u32 = held.parse().map_err(|_| invalid())?;
+    let release = frame.checked_add(held).ok_or_else(invalid)?;
+    Ok([
+        KeyEvent { frame, key, down: true },
+        KeyEvent { frame: release, key, down: false },
+    ])
+}
+
+// `disasm <rom>`: print a listing of the ROM to stdout
+fn disasm(file_name: &str, platform: Option<Platform>) -> Result<(), String> {
+    let (guessed, rom) = load_file(file_name)?;
+    print!("{}", disassemble(&rom, platform.unwrap_or(guessed)));
+    Ok(())
+}
+
+// `info <rom>`: what the ROM needs to run, worked out from the code reachable in it
+fn info(file_name: &str, config: &ConfigArgs) -> Result<(), String> {
+    let (guessed, rom) = load_file(file_name)?;
+    let (known, _) = rom_info(config, &rom)?;
+    // Decode everything so the listing shows which platform the code actually uses
+    let listing = disassemble(&rom, Platform::XoChip);
+    let mut instructions = 0;
+    let mut data = 0;
+    let mut needs = Platform::Chip8;
+    for line in &listing.lines {
+        match &line.kind {
+            LineKind::Code { instruction, .. } => {
+                instructions += 1;
+                needs = needs.max(instruction.platform());
+            },
+            LineKind::Data(_) => data += 1,
+        }
+    }
+
+    println!("file          {}", file_name);
+    println!("size          {} bytes", rom.len());
+    println!("sha1          {}", sha1_hex(&rom));
+    println!("crc32         {:08x}", rom_hash(&rom));
+    println!("extension     {}", guessed.name());
+    println!("needs         {}", needs.name());
+    println!("instructions  {}", instructions);
+    println!("data          {} bytes", data);
+    let capacity = needs.max(guessed).memory_size() - CHIP8_PROGRAM_LOAD_ADDRESS;
+    if rom.len() > capacity {
+        println!("warning       too large for {}, which fits {} bytes", needs.max(guessed).name(), capacity);
+    }
+
+    // Anything the ROM database or settings file adds
+    if let Some(title) = &known.title {
+        println!("title         {}", title);
+    }
+    if let Some(platform) = known.platform {
+        println!("platform      {}", platform.name());
+    }
+    if let Some(tickrate) = known.tickrate {
+        println!("tickrate      {}", tickrate);
+    }
+    let quirks = known.resolve_quirks(known.platform.unwrap_or(guessed));
+    if known.quirks.is_some() || !known.quirk_overrides.is_empty() {
+        let set: Vec<&str> = QUIRK_NAMES.iter().copied().filter(|name| quirks.get(name) == Some(true)).collect();
+        println!("quirks        {}", set.join(" "));
+    }
+    if !known.palette.is_empty() {
+        let colours: Vec<String> = known.palette.iter().map(|[r, g, b]| format!("#{:02x}{:02x}{:02x}", r, g, b)).collect();
+        println!("palette       {}", colours.join(" "));
+    }
+    if !known.keys.is_empty() {
+        let keys: Vec<String> = known.keys.iter().map(|(name, key)| format!("{}={:X}", name, key)).collect();
+        println!("keys          {}", keys.join(" "));
+    }
+    Ok(())
+}
+
+// `assemble <source.8o> <out.ch8>`: write the ROM and print the symbol table
+fn assemble_file(source_name: &str, out_name: &str) -> Result<(), String> {
+    let source = std::fs::read_to_string(source_name).map_err(|e| format!("{}: {}", source_name, e))?;
+    let assembly = assemble(&source).map_err(|e| format!("{}: {}", source_name, e))?;
+    std::fs::write(out_name, &assembly.rom).map_err(|e| format!("{}: {}", out_name, e))?;
+    for (name, addr) in &assembly.symbols {
+        println!("{:03X}  {}", addr, name);
+    }
+    Ok(())
+}
+
+// file handle, returns the platform guessed from the extension and the ROM contents
+fn load_file(file_name: &str) -> Result<(Platform, Vec<u8>), String> {
+    // Open the file in read-binary mode
+    let mut file = File::open(file_name).map_err(|e| format!("{}: {}", file_name, e))?;
+
+    // Read the file contents into a Vec<u8>
+    let mut buffer: Vec<u8> = Vec::new();
+    file.read_to_end(&mut buffer).map_err(|e| format!("{}: {}", file_name, e))?;
+    if buffer.is_empty() {
+        return Err(format!("{}: file is empty", file_name));
+    }
+
+    let platform = Path::new(file_name)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(Platform::from_extension)
+        .unwrap_or_default();
+    Ok((platform, buffer))
+}
@@ -1,17 +1,29 @@
-use crate::config::{CHIP_8_MEMORY_SIZE, CHIP_8_DEFAULT_CHARACTER_SET, CHIP_8_DEFAULT_CHARACTER_SET_LOAD_ADDRESS};
+use crate::config::{
+    CHIP_8_MEMORY_SIZE, CHIP_8_DEFAULT_CHARACTER_SET, CHIP_8_DEFAULT_CHARACTER_SET_LOAD_ADDRESS,
+    CHIP_8_BIG_CHARACTER_SET, CHIP_8_BIG_CHARACTER_SET_LOAD_ADDRESS,
+};
 
 pub struct Chip8Memory {
-    pub memory: [u8; CHIP_8_MEMORY_SIZE],
+    pub memory: Vec<u8>,
 }
 
 impl Chip8Memory {
     pub fn new() -> Self {
-        let mut memory: [u8; CHIP_8_MEMORY_SIZE] = [0u8; CHIP_8_MEMORY_SIZE];
-        
+        Chip8Memory::with_size(CHIP_8_MEMORY_SIZE)
+    }
+
+    // XO-CHIP programs get the full 64 KiB address space
+    pub fn with_size(size: usize) -> Self {
+        let mut memory: Vec<u8> = vec![0u8; size];
+
         // Load the default character set into memory at the specified load address
         for (i, &byte) in CHIP_8_DEFAULT_CHARACTER_SET.iter().enumerate() {
             memory[CHIP_8_DEFAULT_CHARACTER_SET_LOAD_ADDRESS + i] = byte;
         }
+        // And the SUPER-CHIP big font right after it
+        for (i, &byte) in CHIP_8_BIG_CHARACTER_SET.iter().enumerate() {
+            memory[CHIP_8_BIG_CHARACTER_SET_LOAD_ADDRESS + i] = byte;
+        }
 
         Chip8Memory { memory }
     }
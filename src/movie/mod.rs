@@ -0,0 +1,362 @@
+use std::fmt;
+use crate::chip8::Chip8;
+use crate::config::CHIP_8_TOTAL_KEYS;
+use crate::error::MovieError;
+use crate::platform::Platform;
+use crate::quirks::{Quirks, QUIRK_NAMES};
+use crate::rng::{Chip8Rng, VipRng};
+use crate::savestate::crc32;
+
+// A recording of a play session: everything needed to start the same machine,
+// the keys pressed on each frame and a hash of the state every so often to
+// notice when playback stops matching the recording.
+//
+// Movies are stored as text, one entry per line:
+//
+//     chip8-movie 1
+//     rom 1a2b3c4d
+//     seed 12345
+//     random xorshift
+//     platform chip8
+//     quirk shift 0
+//     key 120 5 down
+//     hash 120 89abcdef
+//     frames 3600
+pub const MOVIE_VERSION: u32 = 1;
+
+// Frames between state hashes
+pub const MOVIE_HASH_INTERVAL: u32 = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub frame: u32,
+    pub key: u8,
+    pub down: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub rom_hash: u32,
+    pub seed: u64,
+    // Use the COSMAC VIP random routine instead of the default generator
+    pub vip_random: bool,
+    pub platform: Platform,
+    pub quirks: Quirks,
+    pub events: Vec<KeyEvent>,
+    // (frame, hash of the state at the start of that frame)
+    pub hashes: Vec<(u32, u32)>,
+    pub frames: u32,
+}
+
+// Hash used to identify the ROM a movie was recorded with
+pub fn rom_hash(rom: &[u8]) -> u32 {
+    crc32(rom)
+}
+
+// Hash of the whole machine state, compared during playback. The save state's own
+// trailing checksum is left out; a CRC over data followed by its CRC is constant.
+pub fn state_hash(chip8: &Chip8) -> u32 {
+    let state = chip8.save_state();
+    crc32(&state[..state.len() - 4])
+}
+
+impl Movie {
+    pub fn new(rom: &[u8], seed: u64, vip_random: bool, platform: Platform, quirks: Quirks) -> Self {
+        Movie {
+            rom_hash: rom_hash(rom),
+            seed,
+            vip_random,
+            platform,
+            quirks,
+            events: Vec::new(),
+            hashes: Vec::new(),
+            frames: 0,
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Movie, MovieError> {
+        let mut movie = Movie::new(&[], 0, false, Platform::Chip8, Quirks::default());
+        let mut seen_header = false;
+
+        for (index, line) in text.lines().enumerate() {
+            let error = |message: String| MovieError { line: index + 1, message };
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if words.is_empty() {
+                continue;
+            }
+            if !seen_header {
+                match words.as_slice() {
+                    ["chip8-movie", version] if *version == MOVIE_VERSION.to_string() => seen_header = true,
+                    ["chip8-movie", version] => return Err(error(format!("unsupported movie version {}", version))),
+                    _ => return Err(error("not a movie file".to_string())),
+                }
+                continue;
+            }
+
+            let number = |text: &str| text.parse::<u32>().map_err(|_| error(format!("invalid number '{}'", text)));
+            let hex = |text: &str| u32::from_str_radix(text, 16).map_err(|_| error(format!("invalid hash '{}'", text)));
+            match words.as_slice() {
+                ["rom", hash] => movie.rom_hash = hex(hash)?,
+                ["seed", seed] => movie.seed = seed.parse().map_err(|_| error(format!("invalid seed '{}'", seed)))?,
+                ["random", "xorshift"] => movie.vip_random = false,
+                ["random", "vip"] => movie.vip_random = true,
+                ["platform", name] => {
+                    movie.platform = Platform::from_name(name)
+                        .ok_or_else(|| error(format!("unknown platform '{}'", name)))?;
+                },
+                ["quirk", name, value] => {
+                    let value = match *value {
+                        "0" => false,
+                        "1" => true,
+                        _ => return Err(error(format!("invalid quirk value '{}'", value))),
+                    };
+                    if !movie.quirks.set(name, value) {
+                        return Err(error(format!("unknown quirk '{}'", name)));
+                    }
+                },
+                ["key", frame, key, state] => {
+                    let frame = number(frame)?;
+                    let key = number(key)?;
+                    if key as usize >= CHIP_8_TOTAL_KEYS {
+                        return Err(error(format!("invalid key {}", key)));
+                    }
+                    let down = match *state {
+                        "down" => true,
+                        "up" => false,
+                        _ => return Err(error(format!("invalid key state '{}'", state))),
+                    };
+                    movie.events.push(KeyEvent { frame, key: key as u8, down });
+                },
+                ["hash", frame, hash] => movie.hashes.push((number(frame)?, hex(hash)?)),
+                ["frames", frames] => movie.frames = number(frames)?,
+                _ => return Err(error(format!("unexpected '{}'", line.trim()))),
+            }
+        }
+
+        if !seen_header {
+            return Err(MovieError { line: 1, message: "not a movie file".to_string() });
+        }
+        // Playback walks through these in order
+        movie.events.sort_by_key(|event| event.frame);
+        movie.hashes.sort_by_key(|&(frame, _)| frame);
+        Ok(movie)
+    }
+
+    // Set up an interpreter created for the movie's platform the way the recording started
+    pub fn configure(&self, chip8: &mut Chip8) {
+        chip8.quirks = self.quirks;
+        chip8.rng = if self.vip_random {
+            Box::new(VipRng::from_seed(self.seed as u16))
+        } else {
+            Box::new(Chip8Rng::from_seed(self.seed))
+        };
+    }
+}
+
+impl fmt::Display for Movie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "chip8-movie {}", MOVIE_VERSION)?;
+        writeln!(f, "rom {:08x}", self.rom_hash)?;
+        writeln!(f, "seed {}", self.seed)?;
+        writeln!(f, "random {}", if self.vip_random { "vip" } else { "xorshift" })?;
+        writeln!(f, "platform {}", self.platform.name())?;
+        for name in QUIRK_NAMES {
+            writeln!(f, "quirk {} {}", name, self.quirks.get(name).unwrap() as u8)?;
+        }
+
+        // Interleave keys and hashes in frame order so the file reads as a timeline
+        let mut hashes = self.hashes.iter().peekable();
+        for event in &self.events {
+            while let Some((frame, hash)) = hashes.next_if(|&&(frame, _)| frame <= event.frame) {
+                writeln!(f, "hash {} {:08x}", frame, hash)?;
+            }
+            writeln!(f, "key {} {} {}", event.frame, event.key, if event.down { "down" } else { "up" })?;
+        }
+        for (frame, hash) in hashes {
+            writeln!(f, "hash {} {:08x}", frame, hash)?;
+        }
+        writeln!(f, "frames {}", self.frames)
+    }
+}
+
+// Builds a movie from a live session. Call `record_frame` once per frame, after
+// the frame's input has been applied and before it runs.
+pub struct MovieRecorder {
+    pub movie: Movie,
+    keyboard: [bool; CHIP_8_TOTAL_KEYS],
+}
+
+impl MovieRecorder {
+    pub fn new(movie: Movie) -> Self {
+        MovieRecorder {
+            movie,
+            keyboard: [false; CHIP_8_TOTAL_KEYS],
+        }
+    }
+
+    pub fn record_frame(&mut self, chip8: &Chip8) {
+        let frame = self.movie.frames;
+        for (key, (&now, was)) in chip8.chip8_keyboard.keyboard.iter().zip(self.keyboard.iter_mut()).enumerate() {
+            if now != *was {
+                self.movie.events.push(KeyEvent { frame, key: key as u8, down: now });
+                *was = now;
+            }
+        }
+        if frame.is_multiple_of(MOVIE_HASH_INTERVAL) {
+            self.movie.hashes.push((frame, state_hash(chip8)));
+        }
+        self.movie.frames += 1;
+    }
+
+    pub fn finish(self) -> Movie {
+        self.movie
+    }
+}
+
+// Playback found a different state than was recorded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Desync {
+    pub frame: u32,
+    pub expected: u32,
+    pub actual: u32,
+}
+
+impl fmt::Display for Desync {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "desync at frame {}: expected state {:08x}, got {:08x}", self.frame, self.expected, self.actual)
+    }
+}
+
+// Feeds a movie's input back in. Call `play_frame` where the recorder called
+// `record_frame`.
+pub struct MoviePlayer {
+    pub movie: Movie,
+    frame: u32,
+    next_event: usize,
+    next_hash: usize,
+}
+
+impl MoviePlayer {
+    pub fn new(movie: Movie) -> Self {
+        MoviePlayer {
+            movie,
+            frame: 0,
+            next_event: 0,
+            next_hash: 0,
+        }
+    }
+
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.frame >= self.movie.frames
+    }
+
+    // Apply the input recorded for this frame and check the state hash if one was
+    // taken. Playback carries on after a desync, the error is only a report.
+    pub fn play_frame(&mut self, chip8: &mut Chip8) -> Result<(), Desync> {
+        let frame = self.frame;
+        while let Some(event) = self.movie.events.get(self.next_event).filter(|event| event.frame <= frame) {
+            chip8.chip8_keyboard.keyboard[event.key as usize] = event.down;
+            self.next_event += 1;
+        }
+        self.frame += 1;
+
+        while let Some(&(hash_frame, expected)) = self.movie.hashes.get(self.next_hash).filter(|&&(f, _)| f <= frame) {
+            self.next_hash += 1;
+            if hash_frame == frame {
+                let actual = state_hash(chip8);
+                if actual != expected {
+                    return Err(Desync { frame, expected, actual });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Adds one to V0 forever, so the state changes every frame
+    const ROM: [u8; 4] = [0x70, 0x01, 0x12, 0x00];
+
+    fn machine(movie: &Movie) -> Chip8 {
+        let mut chip8 = Chip8::with_platform(movie.platform);
+        movie.configure(&mut chip8);
+        chip8.chip8_load(&ROM, ROM.len()).unwrap();
+        chip8
+    }
+
+    fn run_frame(chip8: &mut Chip8) {
+        for _ in 0..10 {
+            chip8.tick().unwrap();
+        }
+        chip8.tick_timers();
+    }
+
+    // Record 150 frames with a key held from frame 30 to frame 90
+    fn recorded() -> Movie {
+        let movie = Movie::new(&ROM, 42, false, Platform::Chip8, Platform::Chip8.default_quirks());
+        let mut chip8 = machine(&movie);
+        let mut recorder = MovieRecorder::new(movie);
+        for frame in 0..150 {
+            match frame {
+                30 => chip8.chip8_keyboard.keyboard[5] = true,
+                90 => chip8.chip8_keyboard.keyboard[5] = false,
+                _ => (),
+            }
+            recorder.record_frame(&chip8);
+            run_frame(&mut chip8);
+        }
+        recorder.finish()
+    }
+
+    fn play(movie: Movie) -> Result<(), Desync> {
+        let mut chip8 = machine(&movie);
+        let mut player = MoviePlayer::new(movie);
+        while !player.is_finished() {
+            player.play_frame(&mut chip8)?;
+            run_frame(&mut chip8);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let mut movie = recorded();
+        movie.vip_random = true;
+        movie.platform = Platform::XoChip;
+        movie.quirks.shift = !movie.quirks.shift;
+        assert_eq!(movie.events.len(), 2);
+        assert_eq!(movie.hashes.iter().map(|&(frame, _)| frame).collect::<Vec<_>>(), [0, 60, 120]);
+
+        let text = movie.to_string();
+        assert!(text.starts_with("chip8-movie 1\n"));
+        assert_eq!(Movie::parse(&text).unwrap(), movie);
+    }
+
+    #[test]
+    fn parse_errors_report_the_line() {
+        let error = Movie::parse("chip8-movie 1\nrom 0\nkey 3 16 down\n").unwrap_err();
+        assert_eq!(error.line, 3);
+        assert_eq!(Movie::parse("chip8-movie 9\n").unwrap_err().message, "unsupported movie version 9");
+    }
+
+    #[test]
+    fn playback_matches_the_recording() {
+        assert_eq!(play(recorded()), Ok(()));
+    }
+
+    #[test]
+    fn tampered_hash_desyncs_at_its_frame() {
+        let mut movie = recorded();
+        let (frame, hash) = movie.hashes[1];
+        movie.hashes[1].1 = !hash;
+        assert_eq!(play(movie), Err(Desync { frame, expected: !hash, actual: hash }));
+        assert_eq!(frame, 60);
+    }
+}
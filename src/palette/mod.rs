@@ -0,0 +1,69 @@
+use crate::config::CHIP_8_DEFAULT_PALETTE;
+
+// A named set of colours for palette indexes 0-3: background, plane 1, plane 2
+// and both planes. Plain CHIP-8 and SUPER-CHIP only use the first two.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Theme {
+    pub name: &'static str,
+    pub colours: [[u8; 3]; 4],
+}
+
+pub const THEMES: [Theme; 6] = [
+    Theme { name: "classic", colours: CHIP_8_DEFAULT_PALETTE },
+    // Green phosphor monitor
+    Theme { name: "green", colours: [[0x00, 0x14, 0x00], [0x33, 0xff, 0x66], [0x1a, 0x80, 0x33], [0xa0, 0xff, 0xb8]] },
+    // Amber phosphor monitor
+    Theme { name: "amber", colours: [[0x1a, 0x0f, 0x00], [0xff, 0xb0, 0x00], [0x80, 0x58, 0x00], [0xff, 0xdc, 0x80]] },
+    // Dark pixels on a pale green handheld LCD
+    Theme { name: "lcd", colours: [[0x9b, 0xbc, 0x0f], [0x0f, 0x38, 0x0f], [0x30, 0x62, 0x30], [0x5a, 0x7a, 0x1a]] },
+    Theme { name: "high-contrast", colours: [[0x00, 0x00, 0x00], [0xff, 0xff, 0xff], [0xff, 0xff, 0x00], [0x00, 0xff, 0xff]] },
+    // Okabe-Ito colours, which stay distinct with the common forms of colour blindness
+    Theme { name: "colour-blind", colours: [[0x00, 0x00, 0x00], [0xff, 0xff, 0xff], [0xe6, 0x9f, 0x00], [0x56, 0xb4, 0xe9]] },
+];
+
+impl Theme {
+    pub fn from_name(name: &str) -> Option<Theme> {
+        let name = match name.to_ascii_lowercase().as_str() {
+            "phosphor" => "green".to_string(),
+            "colorblind" | "color-blind" | "colourblind" => "colour-blind".to_string(),
+            "contrast" | "highcontrast" => "high-contrast".to_string(),
+            name => name.to_string(),
+        };
+        THEMES.iter().copied().find(|theme| theme.name == name)
+    }
+
+    // The theme after whichever one `colours` belongs to, or the first one for colours
+    // that aren't a theme
+    pub fn next(colours: &[[u8; 3]; 4]) -> Theme {
+        match THEMES.iter().position(|theme| &theme.colours == colours) {
+            Some(index) => THEMES[(index + 1) % THEMES.len()],
+            None => THEMES[0],
+        }
+    }
+}
+
+// `RRGGBB` hex, optionally with a leading #
+pub fn parse_colour(text: &str) -> Option<[u8; 3]> {
+    let digits = text.strip_prefix('#').unwrap_or(text);
+    if digits.len() != 6 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let value = u32::from_str_radix(digits, 16).ok()?;
+    Some([(value >> 16) as u8, (value >> 8) as u8, value as u8])
+}
+
+// Up to four comma separated colours, background first
+pub fn parse_palette(text: &str) -> Option<Vec<[u8; 3]>> {
+    let colours = text.split(',').map(|colour| parse_colour(colour.trim())).collect::<Option<Vec<_>>>()?;
+    if colours.len() > 4 {
+        return None;
+    }
+    Some(colours)
+}
+
+// Write colours over the start of a palette, leaving the entries they don't reach
+pub fn overlay(palette: &mut [[u8; 3]; 4], colours: &[[u8; 3]]) {
+    for (entry, &colour) in palette.iter_mut().zip(colours) {
+        *entry = colour;
+    }
+}
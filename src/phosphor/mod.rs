@@ -0,0 +1,81 @@
+use crate::screen::Chip8Screen;
+
+// Simulates the slow fade of a CRT's phosphor. Games erase and redraw sprites with
+// XOR, so without it a sprite that moves every frame is dark half the time and
+// flickers. Pixels light up at once but fade out over a few frames. Only what is
+// shown changes; the screen the interpreter sees is left alone.
+#[derive(Debug, Clone)]
+pub struct Phosphor {
+    // Share of its remaining glow a dark pixel loses every 60 Hz frame, from 0 to 1
+    pub decay: f32,
+    // Colour each pixel is glowing with, row by row at the screen's resolution
+    glow: Vec<[f32; 3]>,
+    width: usize,
+    hires: bool,
+    // Whether a pixel was still visibly fading at the last update
+    fading: bool,
+}
+
+impl Phosphor {
+    pub fn new(decay: f32) -> Self {
+        Phosphor { decay: decay.clamp(0.0, 1.0), glow: Vec::new(), width: 0, hires: false, fading: false }
+    }
+
+    // Fade towards what the screen shows now, after `frames` 60 Hz frames
+    pub fn update(&mut self, screen: &Chip8Screen, palette: &[[u8; 3]; 4], frames: f32) {
+        let (width, height) = (screen.width(), screen.height());
+        let background = palette[0].map(f32::from);
+        // Nothing carries over when the resolution changes
+        if self.glow.len() != width * height || self.hires != screen.hires {
+            self.glow = vec![background; width * height];
+            self.width = width;
+            self.hires = screen.hires;
+        }
+        let remaining = (1.0 - self.decay).powf(frames.max(0.0));
+        self.fading = false;
+        for y in 0..height {
+            for x in 0..width {
+                let glow = &mut self.glow[y * width + x];
+                match screen.pixel(x, y) {
+                    0 => for (channel, target) in glow.iter_mut().zip(background) {
+                        *channel = target + (*channel - target) * remaining;
+                        self.fading |= channel.round() != target;
+                    },
+                    pixel => *glow = palette[pixel as usize].map(f32::from),
+                }
+            }
+        }
+    }
+
+    // The glow keeps changing what is shown until every pixel has faded to the background
+    pub fn is_fading(&self) -> bool {
+        self.fading
+    }
+
+    // Colour to show for a pixel, as of the last update
+    pub fn colour(&self, x: usize, y: usize) -> [u8; 3] {
+        self.glow[y * self.width + x].map(|channel| channel.round() as u8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CHIP_8_DEFAULT_PALETTE;
+
+    #[test]
+    fn fading_stops_once_pixels_reach_the_background() {
+        let mut screen = Chip8Screen::new();
+        let mut phosphor = Phosphor::new(0.5);
+        screen.pixels[0][0] = 1;
+        phosphor.update(&screen, &CHIP_8_DEFAULT_PALETTE, 1.0);
+        assert!(!phosphor.is_fading());
+
+        screen.pixels[0][0] = 0;
+        phosphor.update(&screen, &CHIP_8_DEFAULT_PALETTE, 1.0);
+        assert!(phosphor.is_fading());
+        phosphor.update(&screen, &CHIP_8_DEFAULT_PALETTE, 60.0);
+        assert!(!phosphor.is_fading());
+        assert_eq!(phosphor.colour(0, 0), CHIP_8_DEFAULT_PALETTE[0]);
+    }
+}
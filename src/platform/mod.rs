@@ -0,0 +1,57 @@
+use crate::quirks::Quirks;
+use crate::config::{CHIP_8_MEMORY_SIZE, CHIP_8_XO_MEMORY_SIZE};
+
+// The CHIP-8 dialect a ROM was written for. This decides which opcodes are
+// available and which quirks are used unless the caller overrides them. Each
+// platform is a superset of the ones before it, so they are ordered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Platform {
+    #[default]
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+
+impl Platform {
+    pub fn default_quirks(self) -> Quirks {
+        match self {
+            Platform::Chip8 => Quirks::cosmac_vip(),
+            Platform::SuperChip => Quirks::super_chip(),
+            Platform::XoChip => Quirks::xo_chip(),
+        }
+    }
+
+    pub fn memory_size(self) -> usize {
+        match self {
+            Platform::Chip8 | Platform::SuperChip => CHIP_8_MEMORY_SIZE,
+            Platform::XoChip => CHIP_8_XO_MEMORY_SIZE,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Platform::Chip8 => "chip8",
+            Platform::SuperChip => "schip",
+            Platform::XoChip => "xochip",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Platform> {
+        match name.to_ascii_lowercase().as_str() {
+            "chip8" | "chip-8" => Some(Platform::Chip8),
+            "schip" | "superchip" | "super-chip" => Some(Platform::SuperChip),
+            "xochip" | "xo-chip" => Some(Platform::XoChip),
+            _ => None,
+        }
+    }
+
+    // Guess the platform from the conventional ROM file extensions
+    pub fn from_extension(ext: &str) -> Option<Platform> {
+        match ext.to_ascii_lowercase().as_str() {
+            "ch8" | "c8" => Some(Platform::Chip8),
+            "sc8" | "schip" => Some(Platform::SuperChip),
+            "xo8" => Some(Platform::XoChip),
+            _ => None,
+        }
+    }
+}
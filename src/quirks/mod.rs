@@ -0,0 +1,106 @@
+// Behaviours that differ between CHIP-8 interpreters. Each flag is true when the
+// interpreter should use the behaviour described next to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    pub shift: bool,                 // 8XY6/8XYE shift VX in place instead of VY into VX
+    pub load_store_increment_i: bool, // FX55/FX65 leave I pointing past the last register
+    pub jump_vx: bool,               // BNNN becomes BXNN and jumps to XNN + VX
+    pub vf_reset: bool,              // 8XY1/8XY2/8XY3 reset VF to 0
+    pub clip_sprites: bool,          // sprites are clipped at the screen edge instead of wrapping
+    pub display_wait: bool,          // DXYN waits for the next frame before continuing
+}
+
+// Names of the quirks, as used in movie files and on the command line
+pub const QUIRK_NAMES: [&str; 6] = [
+    "shift", "load_store_increment_i", "jump_vx", "vf_reset", "clip_sprites", "display_wait",
+];
+
+impl Quirks {
+    // Original COSMAC VIP interpreter
+    pub fn cosmac_vip() -> Self {
+        Quirks {
+            shift: false,
+            load_store_increment_i: true,
+            jump_vx: false,
+            vf_reset: true,
+            clip_sprites: true,
+            display_wait: true,
+        }
+    }
+
+    // SUPER-CHIP 1.1 on the HP48
+    pub fn super_chip() -> Self {
+        Quirks {
+            shift: true,
+            load_store_increment_i: false,
+            jump_vx: true,
+            vf_reset: false,
+            clip_sprites: true,
+            display_wait: false,
+        }
+    }
+
+    // XO-CHIP as implemented by Octo
+    pub fn xo_chip() -> Self {
+        Quirks {
+            shift: false,
+            load_store_increment_i: true,
+            jump_vx: false,
+            vf_reset: false,
+            clip_sprites: false,
+            display_wait: false,
+        }
+    }
+
+    // Look a preset up by name, as given on the command line
+    pub fn preset(name: &str) -> Option<Quirks> {
+        match name.to_ascii_lowercase().as_str() {
+            "vip" | "cosmac" | "chip8" | "chip-8" => Some(Quirks::cosmac_vip()),
+            "schip" | "superchip" | "super-chip" => Some(Quirks::super_chip()),
+            "xochip" | "xo-chip" | "octo" => Some(Quirks::xo_chip()),
+            _ => None,
+        }
+    }
+
+    // Set a quirk by name, returning false if there is no such quirk
+    pub fn set(&mut self, name: &str, value: bool) -> bool {
+        match self.flag_mut(name) {
+            Some(flag) => {
+                *flag = value;
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn flag_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "shift" => Some(&mut self.shift),
+            "load_store_increment_i" => Some(&mut self.load_store_increment_i),
+            "jump_vx" => Some(&mut self.jump_vx),
+            "vf_reset" => Some(&mut self.vf_reset),
+            "clip_sprites" => Some(&mut self.clip_sprites),
+            "display_wait" => Some(&mut self.display_wait),
+            _ => None,
+        }
+    }
+
+    // Look a quirk up by name
+    pub fn get(&self, name: &str) -> Option<bool> {
+        match name {
+            "shift" => Some(self.shift),
+            "load_store_increment_i" => Some(self.load_store_increment_i),
+            "jump_vx" => Some(self.jump_vx),
+            "vf_reset" => Some(self.vf_reset),
+            "clip_sprites" => Some(self.clip_sprites),
+            "display_wait" => Some(self.display_wait),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks::cosmac_vip()
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::VecDeque;
+use crate::chip8::Chip8;
+use crate::error::SaveStateError;
+
+// Unchanged bytes shorter than this between two changes are stored in the same run,
+// so scattered sprite updates don't each pay for a separate offset
+const MERGE_GAP: usize = 8;
+
+// The bytes of a snapshot that differ from the snapshot taken after it. Rewinding
+// patches these back over the newer snapshot.
+struct Delta {
+    len: usize,
+    runs: Vec<(usize, Vec<u8>)>,
+}
+
+impl Delta {
+    fn between(older: &[u8], newer: &[u8]) -> Delta {
+        // Snapshots of different platforms have different memory sizes, keep the whole thing
+        if older.len() != newer.len() {
+            return Delta { len: older.len(), runs: vec![(0, older.to_vec())] };
+        }
+
+        let mut runs = Vec::new();
+        let mut i = 0;
+        while i < older.len() {
+            if older[i] == newer[i] {
+                i += 1;
+                continue;
+            }
+            let start = i;
+            let mut end = i + 1;
+            let mut j = end;
+            while j < older.len() && j - end < MERGE_GAP {
+                if older[j] != newer[j] {
+                    end = j + 1;
+                }
+                j += 1;
+            }
+            runs.push((start, older[start..end].to_vec()));
+            i = j;
+        }
+        Delta { len: older.len(), runs }
+    }
+
+    fn apply(&self, newer: &[u8]) -> Vec<u8> {
+        let mut older = newer.to_vec();
+        older.resize(self.len, 0);
+        for (start, bytes) in &self.runs {
+            older[*start..*start + bytes.len()].copy_from_slice(bytes);
+        }
+        older
+    }
+
+    fn size(&self) -> usize {
+        self.runs.iter().map(|(_, bytes)| bytes.len()).sum()
+    }
+}
+
+// Ring buffer of recent snapshots for stepping backwards through gameplay. Only
+// the newest snapshot is kept in full, each older one is stored as a delta against
+// the one after it so a frame usually costs a few hundred bytes.
+pub struct Rewind {
+    capacity: usize,
+    latest: Option<Vec<u8>>,
+    history: VecDeque<Delta>,
+}
+
+impl Rewind {
+    // Keep up to `capacity` snapshots before the latest one
+    pub fn new(capacity: usize) -> Self {
+        Rewind {
+            capacity,
+            latest: None,
+            history: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    // Snapshot the interpreter, normally once per frame
+    pub fn record(&mut self, chip8: &Chip8) {
+        let state = chip8.save_state();
+        if let Some(latest) = self.latest.take() {
+            if self.history.len() == self.capacity {
+                self.history.pop_front();
+            }
+            if self.capacity > 0 {
+                self.history.push_back(Delta::between(&latest, &state));
+            }
+        }
+        self.latest = Some(state);
+    }
+
+    // Restore the snapshot before the latest one. Returns false once the history
+    // has run out.
+    pub fn rewind(&mut self, chip8: &mut Chip8) -> Result<bool, SaveStateError> {
+        let (delta, latest) = match (self.history.pop_back(), self.latest.as_ref()) {
+            (Some(delta), Some(latest)) => (delta, latest),
+            _ => return Ok(false),
+        };
+        let state = delta.apply(latest);
+        chip8.load_state(&state)?;
+        self.latest = Some(state);
+        Ok(true)
+    }
+
+    // Number of snapshots that can be rewound to
+    pub fn len(&self) -> usize {
+        self.history.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.history.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.history.clear();
+    }
+
+    // Approximate memory used by the history, in bytes
+    pub fn size(&self) -> usize {
+        let latest = self.latest.as_ref().map_or(0, Vec::len);
+        latest + self.history.iter().map(Delta::size).sum::<usize>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Draw a sprite and change some memory, so every frame differs from the last
+    fn advance(chip8: &mut Chip8, frame: u8) {
+        chip8.chip8_memory.memory[0x300 + frame as usize] = frame;
+        chip8.chip8_screen.chip8_screen_draw_sprite(frame as usize * 3, frame as usize, &[0xF0, 0x90], false);
+        chip8.registers.v[0] = frame;
+    }
+
+    #[test]
+    fn rewinding_restores_earlier_snapshots() {
+        let mut chip8 = Chip8::new();
+        let mut rewind = Rewind::new(10);
+        let mut states = Vec::new();
+        for frame in 0..5 {
+            advance(&mut chip8, frame);
+            rewind.record(&chip8);
+            states.push(chip8.save_state());
+        }
+        assert_eq!(rewind.len(), 4);
+        for expected in states.iter().rev().skip(1) {
+            assert!(rewind.rewind(&mut chip8).unwrap());
+            assert_eq!(&chip8.save_state(), expected);
+        }
+        assert!(!rewind.rewind(&mut chip8).unwrap());
+    }
+
+    #[test]
+    fn history_is_limited_to_capacity() {
+        let mut chip8 = Chip8::new();
+        let mut rewind = Rewind::new(2);
+        for frame in 0..5 {
+            advance(&mut chip8, frame);
+            rewind.record(&chip8);
+        }
+        assert_eq!(rewind.len(), 2);
+        assert!(rewind.rewind(&mut chip8).unwrap());
+        assert!(rewind.rewind(&mut chip8).unwrap());
+        assert_eq!(chip8.registers.v[0], 2);
+        assert!(!rewind.rewind(&mut chip8).unwrap());
+    }
+}
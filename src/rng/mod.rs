@@ -0,0 +1,117 @@
+// Where CXNN gets its random bytes from. The interpreter owns its source so runs
+// can be made reproducible, and its state is part of a save state.
+pub trait RandomSource {
+    // `memory` is the interpreter's memory, for generators that read from it
+    fn next_byte(&mut self, memory: &[u8]) -> u8;
+    fn state(&self) -> u64;
+    fn set_state(&mut self, state: u64);
+    fn kind(&self) -> RngKind;
+}
+
+// The generators the interpreter can be given, recorded in save states so the
+// same kind of source is rebuilt when one is loaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RngKind {
+    Xorshift,
+    Vip,
+}
+
+impl RngKind {
+    // Build a source of this kind resuming from a saved state
+    pub fn source(self, state: u64) -> Box<dyn RandomSource> {
+        let mut source: Box<dyn RandomSource> = match self {
+            RngKind::Xorshift => Box::new(Chip8Rng::from_seed(state)),
+            RngKind::Vip => Box::new(VipRng::default()),
+        };
+        source.set_state(state);
+        source
+    }
+}
+
+// xorshift64* generator, the default source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chip8Rng {
+    state: u64,
+}
+
+impl Chip8Rng {
+    // Seed from the operating system's entropy source
+    pub fn new() -> Self {
+        Chip8Rng::from_seed(rand::random())
+    }
+
+    pub fn from_seed(seed: u64) -> Self {
+        // xorshift gets stuck at zero, so map it to an arbitrary non-zero seed
+        let state = if seed == 0 { 0x9E37_79B9_7F4A_7C15 } else { seed };
+        Chip8Rng { state }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+}
+
+impl Default for Chip8Rng {
+    fn default() -> Self {
+        Chip8Rng::new()
+    }
+}
+
+impl RandomSource for Chip8Rng {
+    fn next_byte(&mut self, _memory: &[u8]) -> u8 {
+        (self.next_u64() >> 56) as u8
+    }
+
+    fn state(&self) -> u64 {
+        self.state
+    }
+
+    fn set_state(&mut self, state: u64) {
+        *self = Chip8Rng::from_seed(state);
+    }
+
+    fn kind(&self) -> RngKind {
+        RngKind::Xorshift
+    }
+}
+
+// Modelled on the COSMAC VIP interpreter's generator. It keeps a 16-bit seed in register R9,
+// steps the low byte, adds the byte of memory it points at in the first page to
+// the high byte and returns the new high byte. On the VIP that page held the
+// interpreter itself; here it holds the built in fonts. The sequence is short
+// and patterned, which some games were tuned around.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct VipRng {
+    r9: u16,
+}
+
+impl VipRng {
+    pub fn from_seed(seed: u16) -> Self {
+        VipRng { r9: seed }
+    }
+}
+
+impl RandomSource for VipRng {
+    fn next_byte(&mut self, memory: &[u8]) -> u8 {
+        let [high, low] = self.r9.to_be_bytes();
+        let low = low.wrapping_add(1);
+        let high = high.wrapping_add(memory.get(low as usize).copied().unwrap_or(0));
+        self.r9 = u16::from_be_bytes([high, low]);
+        high
+    }
+
+    fn state(&self) -> u64 {
+        self.r9 as u64
+    }
+
+    fn set_state(&mut self, state: u64) {
+        self.r9 = state as u16;
+    }
+
+    fn kind(&self) -> RngKind {
+        RngKind::Vip
+    }
+}
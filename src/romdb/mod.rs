@@ -0,0 +1,252 @@
+use std::collections::{BTreeMap, HashMap};
+use serde::Deserialize;
+use sha1::{Digest, Sha1};
+use crate::platform::Platform;
+use crate::quirks::Quirks;
+use crate::palette::{overlay, parse_colour};
+
+// What is known about a single ROM: everything is optional and only the settings
+// that are given replace the usual defaults.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RomInfo {
+    pub title: Option<String>,
+    pub platform: Option<Platform>,
+    // Quirks to start from, before `quirk_overrides` are applied
+    pub quirks: Option<Quirks>,
+    pub quirk_overrides: BTreeMap<String, bool>,
+    // Instructions per 60 Hz frame
+    pub tickrate: Option<u32>,
+    // Colours for palette indexes 0-3, background first
+    pub palette: Vec<[u8; 3]>,
+    // Which CHIP-8 key does what, e.g. ("up", 5)
+    pub keys: BTreeMap<String, u8>,
+}
+
+impl RomInfo {
+    // Replace the settings `other` gives, keeping the rest
+    pub fn merge(&mut self, other: &RomInfo) {
+        if other.title.is_some() {
+            self.title = other.title.clone();
+        }
+        if other.platform.is_some() {
+            self.platform = other.platform;
+        }
+        if other.quirks.is_some() {
+            self.quirks = other.quirks;
+        }
+        self.quirk_overrides.extend(other.quirk_overrides.iter().map(|(name, &value)| (name.clone(), value)));
+        if other.tickrate.is_some() {
+            self.tickrate = other.tickrate;
+        }
+        if !other.palette.is_empty() {
+            self.palette = other.palette.clone();
+        }
+        self.keys.extend(other.keys.iter().map(|(name, &key)| (name.clone(), key)));
+    }
+
+    // The quirks to run with on `platform`
+    pub fn resolve_quirks(&self, platform: Platform) -> Quirks {
+        let mut quirks = self.quirks.unwrap_or_else(|| platform.default_quirks());
+        for (name, &value) in &self.quirk_overrides {
+            quirks.set(name, value);
+        }
+        quirks
+    }
+
+    // The default palette with the colours given here written over it
+    pub fn apply_palette(&self, palette: &mut [[u8; 3]; 4]) {
+        overlay(palette, &self.palette);
+    }
+}
+
+// Hex SHA-1 of a ROM, the key used by the database
+pub fn sha1_hex(rom: &[u8]) -> String {
+    Sha1::digest(rom).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// ROM information keyed by SHA-1, read from the community CHIP-8 database's
+// `programs.json`. Platforms this emulator can't run are left out of an entry.
+#[derive(Debug, Clone, Default)]
+pub struct RomDatabase {
+    roms: HashMap<String, RomInfo>,
+}
+
+impl RomDatabase {
+    pub fn new() -> Self {
+        RomDatabase { roms: HashMap::new() }
+    }
+
+    pub fn parse(json: &str) -> Result<RomDatabase, String> {
+        let programs: Vec<Program> = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        let mut database = RomDatabase::new();
+        for program in programs {
+            for (hash, rom) in program.roms {
+                let mut info = RomInfo { title: program.title.clone(), ..RomInfo::default() };
+                // The first listed platform that can be emulated, with that platform's quirks
+                if let Some((id, platform, quirks)) = rom.platforms.iter()
+                    .find_map(|id| community_platform(id).map(|(platform, quirks)| (id, platform, quirks))) {
+                    info.platform = Some(platform);
+                    info.quirks = Some(quirks);
+                    if let Some(quirky) = rom.quirky_platforms.get(id) {
+                        for (name, &value) in quirky {
+                            if let Some((name, value)) = community_quirk(name, value) {
+                                info.quirk_overrides.insert(name.to_string(), value);
+                            }
+                        }
+                    }
+                }
+                info.tickrate = rom.tickrate.filter(|&tickrate| tickrate > 0);
+                if let Some(colors) = rom.colors {
+                    info.palette = colors.pixels.iter().filter_map(|colour| parse_colour(colour)).collect();
+                }
+                info.keys = rom.keys.into_iter().filter(|&(_, key)| key < 16).collect();
+                database.roms.insert(hash.to_ascii_lowercase(), info);
+            }
+        }
+        Ok(database)
+    }
+
+    pub fn get(&self, hash: &str) -> Option<&RomInfo> {
+        self.roms.get(hash)
+    }
+
+    pub fn lookup(&self, rom: &[u8]) -> Option<&RomInfo> {
+        self.get(&sha1_hex(rom))
+    }
+
+    pub fn len(&self) -> usize {
+        self.roms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.roms.is_empty()
+    }
+}
+
+// Just the parts of `programs.json` that are used; everything else is ignored
+#[derive(Deserialize)]
+struct Program {
+    title: Option<String>,
+    #[serde(default)]
+    roms: HashMap<String, ProgramRom>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ProgramRom {
+    #[serde(default)]
+    platforms: Vec<String>,
+    #[serde(default)]
+    quirky_platforms: HashMap<String, HashMap<String, bool>>,
+    tickrate: Option<u32>,
+    colors: Option<Colors>,
+    #[serde(default)]
+    keys: BTreeMap<String, u8>,
+}
+
+#[derive(Deserialize)]
+struct Colors {
+    #[serde(default)]
+    pixels: Vec<String>,
+}
+
+// The database's platform ids, with the quirks each of them implies
+fn community_platform(id: &str) -> Option<(Platform, Quirks)> {
+    match id {
+        "originalChip8" | "hybridVIP" => Some((Platform::Chip8, Quirks::cosmac_vip())),
+        "modernChip8" => Some((Platform::Chip8, Quirks {
+            shift: false,
+            load_store_increment_i: true,
+            jump_vx: false,
+            vf_reset: false,
+            clip_sprites: true,
+            display_wait: false,
+        })),
+        "chip48" => Some((Platform::Chip8, Quirks {
+            shift: true,
+            load_store_increment_i: true,
+            jump_vx: true,
+            vf_reset: false,
+            clip_sprites: true,
+            display_wait: false,
+        })),
+        "superchip1" | "superchip" => Some((Platform::SuperChip, Quirks::super_chip())),
+        "xochip" => Some((Platform::XoChip, Quirks::xo_chip())),
+        _ => None,
+    }
+}
+
+// Translate one of the database's quirk settings to the name used here
+fn community_quirk(name: &str, value: bool) -> Option<(&'static str, bool)> {
+    match name {
+        "shift" => Some(("shift", value)),
+        "memoryLeaveIUnchanged" => Some(("load_store_increment_i", !value)),
+        "jump" => Some(("jump_vx", value)),
+        "logic" => Some(("vf_reset", value)),
+        "wrap" => Some(("clip_sprites", !value)),
+        "vblank" => Some(("display_wait", value)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Trimmed down entries in the shape of the community database's programs.json
+    const PROGRAMS: &str = r##"[
+        {
+            "title": "Spacefight 2091!",
+            "authors": ["Carsten Soerensen"],
+            "roms": {
+                "D0B6FF8E8BA3E8CB0C0B1B3D0E2F9C0A5B3E1F11": {
+                    "file": "spacefight2091.ch8",
+                    "platforms": ["megachip8", "superchip"],
+                    "quirkyPlatforms": {
+                        "superchip": { "shift": false, "wrap": true, "memoryLeaveIUnchanged": false, "lores": true }
+                    },
+                    "tickrate": 30,
+                    "colors": { "pixels": ["#000000", "#ff8800"] },
+                    "keys": { "left": 4, "right": 6, "a": 5, "bogus": 20 }
+                }
+            }
+        },
+        {
+            "title": "Unplayable",
+            "roms": {
+                "0000000000000000000000000000000000000001": { "platforms": ["megachip8"], "tickrate": 0 }
+            }
+        }
+    ]"##;
+
+    #[test]
+    fn programs_entry_resolves_to_quirks() {
+        let database = RomDatabase::parse(PROGRAMS).unwrap();
+        assert_eq!(database.len(), 2);
+
+        let info = database.get("d0b6ff8e8ba3e8cb0c0b1b3d0e2f9c0a5b3e1f11").unwrap();
+        assert_eq!(info.title.as_deref(), Some("Spacefight 2091!"));
+        assert_eq!(info.platform, Some(Platform::SuperChip));
+        assert_eq!(info.tickrate, Some(30));
+        assert_eq!(info.palette, [[0, 0, 0], [0xff, 0x88, 0x00]]);
+        assert_eq!(info.keys.get("a"), Some(&5));
+        assert_eq!(info.keys.get("bogus"), None);
+        // wrap and memoryLeaveIUnchanged mean the opposite of the quirks here
+        let expected = Quirks {
+            shift: false,
+            clip_sprites: false,
+            load_store_increment_i: true,
+            ..Quirks::super_chip()
+        };
+        assert_eq!(info.resolve_quirks(Platform::SuperChip), expected);
+    }
+
+    #[test]
+    fn unknown_platforms_keep_the_defaults() {
+        let database = RomDatabase::parse(PROGRAMS).unwrap();
+        let info = database.get("0000000000000000000000000000000000000001").unwrap();
+        assert_eq!(info.platform, None);
+        assert_eq!(info.tickrate, None);
+        assert_eq!(info.resolve_quirks(Platform::XoChip), Quirks::xo_chip());
+    }
+}
@@ -0,0 +1,224 @@
+use crate::error::SaveStateError;
+
+// Save state layout: the magic, a little endian u16 version, the machine state
+// written field by field, and a CRC-32 of everything before it.
+pub const SAVE_STATE_MAGIC: &[u8; 4] = b"C8SS";
+pub const SAVE_STATE_VERSION: u16 = 2;
+
+// Appends little endian fields to a buffer
+pub struct StateWriter {
+    buffer: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        let mut writer = StateWriter { buffer: Vec::new() };
+        writer.bytes(SAVE_STATE_MAGIC);
+        writer.u16(SAVE_STATE_VERSION);
+        writer
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.buffer.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    // Finish the state by appending the checksum
+    pub fn finish(mut self) -> Vec<u8> {
+        let checksum = crc32(&self.buffer);
+        self.u32(checksum);
+        self.buffer
+    }
+}
+
+impl Default for StateWriter {
+    fn default() -> Self {
+        StateWriter::new()
+    }
+}
+
+// Reads fields back in the order they were written, after checking the
+// header and checksum
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Self, SaveStateError> {
+        if data.len() < SAVE_STATE_MAGIC.len() + 2 + 4 {
+            return Err(SaveStateError::Truncated);
+        }
+        if &data[..4] != SAVE_STATE_MAGIC {
+            return Err(SaveStateError::BadMagic);
+        }
+        let (body, checksum) = data.split_at(data.len() - 4);
+        let expected = u32::from_le_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]);
+        if crc32(body) != expected {
+            return Err(SaveStateError::ChecksumMismatch);
+        }
+
+        let mut reader = StateReader { data: body, position: 4 };
+        let version = reader.u16()?;
+        if version != SAVE_STATE_VERSION {
+            return Err(SaveStateError::UnsupportedVersion(version));
+        }
+        Ok(reader)
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], SaveStateError> {
+        let end = self.position + len;
+        if end > self.data.len() {
+            return Err(SaveStateError::Truncated);
+        }
+        let bytes = &self.data[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, SaveStateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, SaveStateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            other => Err(SaveStateError::Invalid(format!("{} is not a boolean", other))),
+        }
+    }
+
+    pub fn u16(&mut self) -> Result<u16, SaveStateError> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, SaveStateError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, SaveStateError> {
+        let mut value = [0u8; 8];
+        value.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_le_bytes(value))
+    }
+
+    // Every field must have been consumed for the state to be valid
+    pub fn finish(self) -> Result<(), SaveStateError> {
+        if self.position != self.data.len() {
+            return Err(SaveStateError::Invalid("trailing data".to_string()));
+        }
+        Ok(())
+    }
+}
+
+// CRC-32 as used by zlib and PNG
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Chip8;
+    use crate::platform::Platform;
+    use crate::rng::{RandomSource, VipRng};
+
+    fn saved_machine() -> Vec<u8> {
+        let mut chip8 = Chip8::with_platform(Platform::XoChip);
+        chip8.rng = Box::new(VipRng::from_seed(0x1234));
+        chip8.registers.v[3] = 0x42;
+        chip8.registers.i = 0x0321;
+        chip8.registers.pc = 0x0204;
+        chip8.chip8_screen.planes = 0b10;
+        chip8.chip8_screen.pixels[5][7] = 0b11;
+        chip8.save_state()
+    }
+
+    // Recompute the checksum after editing a saved state
+    fn resealed(mut data: Vec<u8>) -> Vec<u8> {
+        let body = data.len() - 4;
+        let checksum = crc32(&data[..body]);
+        data[body..].copy_from_slice(&checksum.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn round_trip_restores_the_machine_and_random_source() {
+        let data = saved_machine();
+        let mut restored = Chip8::new();
+        restored.load_state(&data).unwrap();
+        assert_eq!(restored.save_state(), data);
+        assert_eq!(restored.platform, Platform::XoChip);
+        assert_eq!(restored.registers.v[3], 0x42);
+        assert_eq!(restored.chip8_screen.pixels[5][7], 0b11);
+
+        // The VIP generator is rebuilt rather than the xorshift one Chip8::new made
+        let mut expected = VipRng::from_seed(0x1234);
+        let memory = restored.chip8_memory.memory.clone();
+        for _ in 0..8 {
+            assert_eq!(restored.rng.next_byte(&memory), expected.next_byte(&memory));
+        }
+    }
+
+    #[test]
+    fn bad_magic_is_rejected() {
+        let mut data = saved_machine();
+        data[0] = b'X';
+        assert_eq!(Chip8::new().load_state(&data), Err(SaveStateError::BadMagic));
+    }
+
+    #[test]
+    fn bad_checksum_is_rejected() {
+        let mut data = saved_machine();
+        data[100] ^= 0x01;
+        assert_eq!(Chip8::new().load_state(&data), Err(SaveStateError::ChecksumMismatch));
+    }
+
+    #[test]
+    fn truncated_states_are_rejected() {
+        let data = saved_machine();
+        assert_eq!(Chip8::new().load_state(&data[..8]), Err(SaveStateError::Truncated));
+
+        // A shortened body with a valid checksum runs out of fields
+        let mut short = data[..data.len() - 20].to_vec();
+        short.extend_from_slice(&[0; 4]);
+        assert_eq!(Chip8::new().load_state(&resealed(short)), Err(SaveStateError::Truncated));
+    }
+
+    #[test]
+    fn unknown_planes_are_rejected() {
+        let mut chip8 = Chip8::with_platform(Platform::XoChip);
+        chip8.chip8_screen.planes = 0b100;
+        let data = chip8.save_state();
+        assert!(matches!(Chip8::new().load_state(&data), Err(SaveStateError::Invalid(_))));
+    }
+}
@@ -0,0 +1,155 @@
+use std::time::{Duration, Instant};
+use crate::config::CHIP_8_TIMER_HZ;
+
+// Longest stretch of time made up for in one go. After a stall, such as the
+// window being dragged, the machine skips ahead instead of racing to catch up.
+const MAX_CATCH_UP: Duration = Duration::from_millis(100);
+
+// Work due since the scheduler was last advanced
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Work {
+    pub instructions: usize,
+    pub timer_ticks: u32,
+}
+
+impl Work {
+    // Split the instructions into one batch per timer tick, so the timers tick in
+    // between them rather than all at the end. Each batch is followed by a timer
+    // tick if the flag is set. There is always at least one batch.
+    pub fn batches(&self) -> impl Iterator<Item = (usize, bool)> {
+        let Work { instructions, timer_ticks } = *self;
+        let count = timer_ticks.max(1) as usize;
+        (0..count).map(move |batch| {
+            let size = instructions * (batch + 1) / count - instructions * batch / count;
+            (size, batch < timer_ticks as usize)
+        })
+    }
+}
+
+// Decides how many instructions to run and how often to tick the timers from a
+// monotonic clock, so emulation speed doesn't depend on how often frames are drawn.
+// The timers always tick at 60 Hz. Normally the CPU runs however many instructions
+// the elapsed time is worth; frame locked, it runs exactly the per frame share on
+// each timer tick, so the same input always produces the same result.
+pub struct Scheduler {
+    pub instructions_per_second: u32,
+    pub frame_locked: bool,
+    last: Option<Instant>,
+    // Fractions of instructions and timer ticks carried over between calls
+    instruction_debt: f64,
+    timer_debt: f64,
+}
+
+impl Scheduler {
+    pub fn new(instructions_per_second: u32, frame_locked: bool) -> Self {
+        Scheduler {
+            instructions_per_second,
+            frame_locked,
+            last: None,
+            instruction_debt: 0.0,
+            timer_debt: 0.0,
+        }
+    }
+
+    // Instructions run on each timer tick when frame locked
+    pub fn instructions_per_frame(&self) -> usize {
+        (self.instructions_per_second as f64 / CHIP_8_TIMER_HZ as f64).round() as usize
+    }
+
+    // Forget the time that passed, e.g. while paused, so resuming doesn't jump ahead
+    pub fn reset(&mut self) {
+        self.last = None;
+        self.instruction_debt = 0.0;
+        self.timer_debt = 0.0;
+    }
+
+    pub fn advance(&mut self, now: Instant) -> Work {
+        let elapsed = match self.last {
+            Some(last) => now.saturating_duration_since(last).min(MAX_CATCH_UP),
+            None => Duration::ZERO,
+        };
+        self.last = Some(now);
+        let seconds = elapsed.as_secs_f64();
+
+        self.timer_debt += seconds * CHIP_8_TIMER_HZ as f64;
+        let timer_ticks = self.timer_debt.floor();
+        self.timer_debt -= timer_ticks;
+        let timer_ticks = timer_ticks as u32;
+
+        let instructions = if self.frame_locked {
+            timer_ticks as usize * self.instructions_per_frame()
+        } else {
+            self.instruction_debt += seconds * self.instructions_per_second as f64;
+            let instructions = self.instruction_debt.floor();
+            self.instruction_debt -= instructions;
+            instructions as usize
+        };
+        Work { instructions, timer_ticks }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Advance a scheduler started at `start` in steps of `step`, adding up the work
+    fn run_for(scheduler: &mut Scheduler, start: Instant, step: Duration, steps: u32) -> Work {
+        let mut total = Work::default();
+        for n in 1..=steps {
+            let work = scheduler.advance(start + step * n);
+            total.instructions += work.instructions;
+            total.timer_ticks += work.timer_ticks;
+        }
+        total
+    }
+
+    #[test]
+    fn work_follows_elapsed_time() {
+        let start = Instant::now();
+        let mut scheduler = Scheduler::new(600, false);
+        assert_eq!(scheduler.advance(start), Work::default());
+        let work = run_for(&mut scheduler, start, Duration::from_millis(50), 20);
+        assert_eq!(work, Work { instructions: 600, timer_ticks: 60 });
+
+        // Fractions carry over instead of being lost on every call
+        let start = start + Duration::from_secs(1);
+        let work = run_for(&mut scheduler, start, Duration::from_millis(25), 40);
+        assert_eq!(work, Work { instructions: 600, timer_ticks: 60 });
+    }
+
+    #[test]
+    fn catch_up_is_limited_after_a_stall() {
+        let start = Instant::now();
+        let mut scheduler = Scheduler::new(600, false);
+        scheduler.advance(start);
+        assert_eq!(scheduler.advance(start + Duration::from_secs(5)), Work { instructions: 60, timer_ticks: 6 });
+    }
+
+    #[test]
+    fn reset_forgets_the_time_that_passed() {
+        let start = Instant::now();
+        let mut scheduler = Scheduler::new(600, false);
+        scheduler.advance(start);
+        scheduler.reset();
+        assert_eq!(scheduler.advance(start + Duration::from_millis(50)), Work::default());
+    }
+
+    #[test]
+    fn frame_locked_runs_a_whole_share_per_tick() {
+        let start = Instant::now();
+        let mut scheduler = Scheduler::new(700, true);
+        assert_eq!(scheduler.instructions_per_frame(), 12);
+        scheduler.advance(start);
+        // Too short for a timer tick, so nothing runs
+        assert_eq!(scheduler.advance(start + Duration::from_millis(10)), Work::default());
+        assert_eq!(scheduler.advance(start + Duration::from_millis(50)), Work { instructions: 36, timer_ticks: 3 });
+    }
+
+    #[test]
+    fn batches_split_instructions_between_timer_ticks() {
+        let work = Work { instructions: 10, timer_ticks: 3 };
+        assert_eq!(work.batches().collect::<Vec<_>>(), [(3, true), (3, true), (4, true)]);
+        let work = Work { instructions: 5, timer_ticks: 0 };
+        assert_eq!(work.batches().collect::<Vec<_>>(), [(5, false)]);
+    }
+}
@@ -1,44 +1,215 @@
-use crate::config::{CHIP_8_HEIGHT, CHIP_8_WIDTH};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use crate::capture::create_timestamped;
+use crate::filter::{render, Filter};
+use crate::config::{CHIP_8_HEIGHT, CHIP_8_WIDTH, CHIP_8_HIRES_HEIGHT, CHIP_8_HIRES_WIDTH, CHIP_8_TOTAL_PLANES};
+use crate::savestate::crc32;
 
+// The framebuffer is always allocated at the SUPER-CHIP high resolution; in low
+// resolution mode only the top left CHIP_8_WIDTH x CHIP_8_HEIGHT pixels are used.
+// Each pixel holds one bit per XO-CHIP bitplane, so its value is a palette index 0-3.
 pub struct Chip8Screen {
-    pub pixels: [[bool; CHIP_8_WIDTH]; CHIP_8_HEIGHT],
+    pub pixels: [[u8; CHIP_8_HIRES_WIDTH]; CHIP_8_HIRES_HEIGHT],
+    pub hires: bool,
+    // Bitmask of the planes affected by drawing, clearing and scrolling (FN01)
+    pub planes: u8,
 }
 
 impl Chip8Screen {
     pub fn new() -> Self {
         Chip8Screen {
-            pixels: [[false; CHIP_8_WIDTH]; CHIP_8_HEIGHT],
+            pixels: [[0; CHIP_8_HIRES_WIDTH]; CHIP_8_HIRES_HEIGHT],
+            hires: false,
+            planes: 1,
         }
     }
 
+    pub fn width(&self) -> usize {
+        if self.hires { CHIP_8_HIRES_WIDTH } else { CHIP_8_WIDTH }
+    }
+
+    pub fn height(&self) -> usize {
+        if self.hires { CHIP_8_HIRES_HEIGHT } else { CHIP_8_HEIGHT }
+    }
+
+    // Switch between 64x32 and 128x64, clearing every plane like SUPER-CHIP does
+    pub fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.pixels = [[0; CHIP_8_HIRES_WIDTH]; CHIP_8_HIRES_HEIGHT];
+    }
+
+    pub fn select_planes(&mut self, planes: u8) {
+        self.planes = planes & 0b11;
+    }
+
+    // Number of planes a sprite has to provide data for
+    pub fn selected_plane_count(&self) -> usize {
+        self.planes.count_ones() as usize
+    }
+
     pub fn set_screen(&mut self, x: usize, y: usize) {
-        self.pixels[y][x] ^= true;
+        self.pixels[y][x] ^= 1;
     }
 
     pub fn is_set_screen(&self, x: usize, y: usize ) -> bool {
+        self.pixels[y][x] != 0
+    }
+
+    // Palette index of a pixel, combining both bitplanes
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
         self.pixels[y][x]
     }
 
-    pub fn chip8_screen_draw_sprite(&mut self, x: usize, y: usize, sprite_iter: &[u8]) -> u8 {
+    // Draws an 8 pixel wide sprite with its origin wrapped onto the screen. With `clip` set, the
+    // parts of the sprite that run past the right or bottom edge are dropped instead of wrapping.
+    // When several planes are selected the data for each plane follows the previous one.
+    pub fn chip8_screen_draw_sprite(&mut self, x: usize, y: usize, sprite_iter: &[u8], clip: bool) -> u8 {
         let mut pixel_collison: u8 = 0;
-        for (ly, sprite_byte) in sprite_iter.iter().enumerate() {
-            for lx in 0..8 {
-                if (sprite_byte & (0b10000000 >> lx)) != 0 {
-                    let px = (x + lx) % CHIP_8_WIDTH;
-                    let py = (y + ly) % CHIP_8_HEIGHT;
+        for (plane, data) in self.plane_data(sprite_iter) {
+            let rows = data.iter().map(|&byte| (byte as u16) << 8);
+            pixel_collison |= self.draw_rows(plane, x, y, rows, clip);
+        }
+        pixel_collison
+    }
 
-                    if self.is_set_screen(px, py) {
+    // Draws a 16x16 SUPER-CHIP sprite stored as 16 pairs of bytes per plane
+    pub fn chip8_screen_draw_large_sprite(&mut self, x: usize, y: usize, sprite_iter: &[u8], clip: bool) -> u8 {
+        let mut pixel_collison: u8 = 0;
+        for (plane, data) in self.plane_data(sprite_iter) {
+            let rows = data.chunks(2).map(|pair| {
+                let low = pair.get(1).copied().unwrap_or(0);
+                (pair[0] as u16) << 8 | low as u16
+            });
+            pixel_collison |= self.draw_rows(plane, x, y, rows, clip);
+        }
+        pixel_collison
+    }
+
+    // Split sprite data between the selected planes, pairing each chunk with its plane bit
+    fn plane_data<'a>(&self, sprite_iter: &'a [u8]) -> Vec<(u8, &'a [u8])> {
+        let count = self.selected_plane_count();
+        if count == 0 {
+            return Vec::new();
+        }
+        let len = sprite_iter.len() / count;
+        (0..CHIP_8_TOTAL_PLANES)
+            .map(|plane| 1u8 << plane)
+            .filter(|bit| self.planes & bit != 0)
+            .zip(sprite_iter.chunks(len.max(1)))
+            .collect()
+    }
+
+    fn draw_rows(&mut self, plane: u8, x: usize, y: usize, rows: impl Iterator<Item = u16>, clip: bool) -> u8 {
+        let (width, height) = (self.width(), self.height());
+        let mut pixel_collison: u8 = 0;
+        let x = x % width;
+        let y = y % height;
+        for (ly, row) in rows.enumerate() {
+            for lx in 0..16 {
+                if (row & (0x8000 >> lx)) != 0 {
+                    if clip && (x + lx >= width || y + ly >= height) {
+                        continue;
+                    }
+                    let px = (x + lx) % width;
+                    let py = (y + ly) % height;
+
+                    if self.pixels[py][px] & plane != 0 {
                         pixel_collison = 1;
                     }
 
-                    self.set_screen(px, py);
+                    self.pixels[py][px] ^= plane;
                 }
             }
         }
         pixel_collison
     }
 
+    // Move the selected planes by (dx, dy) pixels, filling the uncovered area with blank pixels
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let (width, height) = (self.width() as isize, self.height() as isize);
+        let planes = self.planes;
+        let old = self.pixels;
+        for y in 0..height {
+            for x in 0..width {
+                let (sx, sy) = (x - dx, y - dy);
+                let moved = if sx >= 0 && sx < width && sy >= 0 && sy < height {
+                    old[sy as usize][sx as usize] & planes
+                } else {
+                    0
+                };
+                let pixel = &mut self.pixels[y as usize][x as usize];
+                *pixel = (*pixel & !planes) | moved;
+            }
+        }
+    }
+
+    pub fn scroll_down(&mut self, n: usize) {
+        self.scroll(0, n as isize);
+    }
+
+    pub fn scroll_up(&mut self, n: usize) {
+        self.scroll(0, -(n as isize));
+    }
+
+    pub fn scroll_right(&mut self, n: usize) {
+        self.scroll(n as isize, 0);
+    }
+
+    pub fn scroll_left(&mut self, n: usize) {
+        self.scroll(-(n as isize), 0);
+    }
+
+    // Clears the selected planes only, leaving the others untouched
     pub fn clear_screen(&mut self) {
-        self.pixels = [[false; CHIP_8_WIDTH]; CHIP_8_HEIGHT];
+        let planes = self.planes;
+        for row in self.pixels.iter_mut() {
+            for pixel in row.iter_mut() {
+                *pixel &= !planes;
+            }
+        }
     }
-}
\ No newline at end of file
+
+    // Text rendering of the visible screen, one line per row. Each pixel is shown
+    // as '.', '#' for plane 1, '+' for plane 2 or '@' for both.
+    pub fn to_ascii(&self) -> String {
+        let mut text = String::with_capacity((self.width() + 1) * self.height());
+        for row in self.pixels.iter().take(self.height()) {
+            for &pixel in row.iter().take(self.width()) {
+                text.push(['.', '#', '+', '@'][pixel as usize]);
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    // Hash of the visible screen, to compare output against a known good run
+    pub fn hash(&self) -> u32 {
+        let mut data = vec![self.hires as u8];
+        for row in self.pixels.iter().take(self.height()) {
+            data.extend_from_slice(&row[..self.width()]);
+        }
+        crc32(&data)
+    }
+
+    // Encode the visible screen as a PNG, run through `filters` and then with every
+    // pixel drawn as a `scale` x `scale` square
+    pub fn write_png<W: Write>(&self, writer: W, palette: &[[u8; 3]; 4], filters: &[Filter], scale: usize) -> Result<(), png::EncodingError> {
+        render(self, palette, filters).resize(scale).write_png(writer)
+    }
+
+    // Save a screenshot beside the ROM, named after it and the current UTC time,
+    // e.g. pong.ch8-20261018-153012.png. Returns the path written.
+    pub fn save_screenshot(&self, rom_path: &Path, palette: &[[u8; 3]; 4], filters: &[Filter], scale: usize) -> io::Result<PathBuf> {
+        let (file, path) = create_timestamped(rom_path, "png")?;
+        let written = self.write_png(BufWriter::new(file), palette, filters, scale).map_err(|e| match e {
+            png::EncodingError::IoError(e) => e,
+            e => io::Error::other(e.to_string()),
+        });
+        // Don't leave a broken image behind
+        if let Err(e) = written {
+            let _ = std::fs::remove_file(&path);
+            return Err(e);
+        }
+        Ok(path)
+    }
+}
@@ -0,0 +1,342 @@
+use sdl2::event::Event;
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::{Canvas, Texture, TextureCreator};
+use sdl2::video::{FullscreenType, Window, WindowContext};
+use sdl2::EventPump;
+use std::collections::{BTreeMap, HashMap};
+use std::time::Instant;
+use crate::frontend::{Display, Input, InputEvent};
+use crate::screen::Chip8Screen;
+use crate::phosphor::Phosphor;
+use crate::filter::{self, Filter, Image};
+use crate::config::{CHIP_8_DEFAULT_PALETTE, CHIP_8_HIRES_HEIGHT, CHIP_8_HIRES_WIDTH, CHIP_8_TIMER_HZ, CHIP_8_TOTAL_KEYS};
+
+// Open a resizable window of the given size and return its display and input halves
+pub fn init(title: &str, width: u32, height: u32, fullscreen: bool) -> Result<(SdlDisplay, SdlInput), String> {
+    let sdl_context = sdl2::init()?;
+    let video_subsystem = sdl_context.video()?;
+    let mut window = video_subsystem.window(title, width, height);
+    window.position_centered().resizable().opengl();
+    if fullscreen {
+        window.fullscreen_desktop();
+    }
+    let window = window.build().map_err(|e| e.to_string())?;
+
+    // Keep pixels sharp when the texture is stretched
+    sdl2::hint::set("SDL_RENDER_SCALE_QUALITY", "nearest");
+    let mut canvas = window.into_canvas().present_vsync().build().map_err(|e| e.to_string())?;
+    canvas.clear();
+    canvas.present();
+
+    let texture_creator = canvas.texture_creator();
+    let texture = create_texture(&texture_creator, 1)?;
+
+    let event_pump = sdl_context.event_pump()?;
+    let display = SdlDisplay {
+        canvas,
+        texture_creator,
+        texture,
+        texture_scale: 1,
+        frame: Vec::new(),
+        frame_size: (0, 0),
+        rendered: None,
+        palette: CHIP_8_DEFAULT_PALETTE,
+        phosphor: None,
+        filters: Vec::new(),
+        last_present: Instant::now(),
+    };
+    Ok((display, SdlInput { event_pump, keymap: Keymap::default() }))
+}
+
+pub struct SdlDisplay {
+    canvas: Canvas<Window>,
+    texture_creator: TextureCreator<WindowContext>,
+    texture: Texture,
+    // How many times bigger than the high resolution screen the texture is
+    texture_scale: usize,
+    // RGB pixels last uploaded to the texture, and their width and height
+    frame: Vec<u8>,
+    frame_size: (usize, usize),
+    // What the picture in the texture was made from, unless the phosphor was still fading
+    rendered: Option<RenderInput>,
+    pub palette: [[u8; 3]; 4],
+    // Fades pixels out instead of switching them off, when set
+    pub phosphor: Option<Phosphor>,
+    // Post-processing run on every frame before it's shown
+    pub filters: Vec<Filter>,
+    last_present: Instant,
+}
+
+// Everything the picture depends on besides the phosphor's glow
+#[derive(Debug, Clone, PartialEq)]
+struct RenderInput {
+    screen: u32,
+    palette: [[u8; 3]; 4],
+    filters: Vec<Filter>,
+    phosphor: bool,
+}
+
+// One texture big enough for either resolution after filters that make the picture
+// `scale` times bigger. The picture is drawn from its top left corner.
+fn create_texture(texture_creator: &TextureCreator<WindowContext>, scale: usize) -> Result<Texture, String> {
+    let (width, height) = (CHIP_8_HIRES_WIDTH * scale, CHIP_8_HIRES_HEIGHT * scale);
+    texture_creator.create_texture_streaming(PixelFormatEnum::RGB24, width as u32, height as u32)
+        .map_err(|e| e.to_string())
+}
+
+impl SdlDisplay {
+    pub fn toggle_fullscreen(&mut self) -> Result<(), String> {
+        let window = self.canvas.window_mut();
+        let fullscreen = match window.fullscreen_state() {
+            FullscreenType::Off => FullscreenType::Desktop,
+            _ => FullscreenType::Off,
+        };
+        window.set_fullscreen(fullscreen)
+    }
+
+    // Run the screen through the phosphor and filters and upload the result
+    fn render(&mut self, screen: &Chip8Screen) -> Result<(), String> {
+        // Each point in the colour of the planes it is set in, or of its remaining glow
+        let image = match &self.phosphor {
+            Some(phosphor) => {
+                let (width, height) = (screen.width(), screen.height());
+                let pixels = (0..width * height).map(|i| phosphor.colour(i % width, i / width)).collect();
+                filter::apply(Image::new(width, height, pixels), &self.filters)
+            },
+            None => filter::render(screen, &self.palette, &self.filters),
+        };
+        let (width, height) = (image.width, image.height);
+        self.frame_size = (width, height);
+
+        let scale = filter::scale(&self.filters);
+        if scale != self.texture_scale {
+            let texture = create_texture(&self.texture_creator, scale)?;
+            // Textures otherwise live as long as the renderer
+            unsafe { std::mem::replace(&mut self.texture, texture).destroy() };
+            self.texture_scale = scale;
+            self.frame.clear();
+        }
+        let frame = image.to_rgb();
+        // A slow fade can round to the same colours as last time, only upload changes
+        if frame != self.frame {
+            let area = Rect::new(0, 0, width as u32, height as u32);
+            self.texture.update(area, &frame, width * 3).map_err(|e| e.to_string())?;
+            self.frame = frame;
+        }
+        Ok(())
+    }
+}
+
+impl Display for SdlDisplay {
+    fn present(&mut self, screen: &Chip8Screen) -> Result<(), String> {
+        // The glow fades by real time, so it looks the same at any refresh rate
+        let now = Instant::now();
+        let frames = (now - self.last_present).as_secs_f32() * CHIP_8_TIMER_HZ as f32;
+        self.last_present = now;
+        if let Some(phosphor) = self.phosphor.as_mut() {
+            phosphor.update(screen, &self.palette, frames);
+        }
+
+        // Only redo the filters when what they start from has changed. While the
+        // phosphor fades the picture changes every frame.
+        let fading = self.phosphor.as_ref().is_some_and(Phosphor::is_fading);
+        let input = RenderInput {
+            screen: screen.hash(),
+            palette: self.palette,
+            filters: self.filters.clone(),
+            phosphor: self.phosphor.is_some(),
+        };
+        if fading || self.rendered.as_ref() != Some(&input) {
+            self.render(screen)?;
+            self.rendered = if fading { None } else { Some(input) };
+        }
+        let (width, height) = self.frame_size;
+        let area = Rect::new(0, 0, width as u32, height as u32);
+
+        // Fill the borders around the screen with the background colour
+        let [r, g, b] = self.palette[0];
+        self.canvas.set_draw_color(Color::RGB(r, g, b));
+        self.canvas.clear();
+        let target = letterbox(self.canvas.output_size()?, width as u32, height as u32);
+        self.canvas.copy(&self.texture, area, target)?;
+        self.canvas.present();
+        Ok(())
+    }
+
+    fn set_title(&mut self, title: &str) -> Result<(), String> {
+        self.canvas.window_mut().set_title(title).map_err(|e| e.to_string())
+    }
+}
+
+// Where a screen of `width` x `height` pixels goes in the window: the largest whole
+// multiple of its size that fits, centred. A window smaller than the screen gets it
+// shrunk to fit, keeping its shape.
+fn letterbox((window_width, window_height): (u32, u32), width: u32, height: u32) -> Rect {
+    let scale = (window_width / width).min(window_height / height);
+    let (target_width, target_height) = if scale > 0 {
+        (width * scale, height * scale)
+    } else {
+        let target_width = window_width.min(window_height * width / height);
+        (target_width, target_width * height / width)
+    };
+    Rect::new(
+        ((window_width - target_width) / 2) as i32,
+        ((window_height - target_height) / 2) as i32,
+        target_width.max(1),
+        target_height.max(1))
+}
+
+pub struct SdlInput {
+    event_pump: EventPump,
+    pub keymap: Keymap,
+}
+
+impl Input for SdlInput {
+    fn poll(&mut self) -> Vec<InputEvent> {
+        let mut events = Vec::new();
+        for evt in self.event_pump.poll_iter() {
+            let event = match evt {
+                Event::Quit{..} | Event::KeyDown{keycode: Some(Keycode::Escape), ..} => Some(InputEvent::Quit),
+                // F1-F4 load a save state slot, shift+F1-F4 save to it
+                Event::KeyDown{keycode: Some(key), keymod, repeat: false, ..} if state_slot(key).is_some() => {
+                    let slot = state_slot(key).unwrap();
+                    if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                        Some(InputEvent::SaveState(slot))
+                    } else {
+                        Some(InputEvent::LoadState(slot))
+                    }
+                },
+                // F12 takes a screenshot at native resolution, shift+F12 at the window's scale
+                Event::KeyDown{keycode: Some(Keycode::F12), keymod, repeat: false, ..} => {
+                    Some(InputEvent::Screenshot { scaled: keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) })
+                },
+                // F11 and alt+enter switch between a window and fullscreen
+                Event::KeyDown{keycode: Some(Keycode::F11), repeat: false, ..} => Some(InputEvent::ToggleFullscreen),
+                Event::KeyDown{keycode: Some(Keycode::Return), keymod, repeat: false, ..}
+                    if keymod.intersects(Mod::LALTMOD | Mod::RALTMOD) => Some(InputEvent::ToggleFullscreen),
+                // F6 cycles through the colour themes
+                Event::KeyDown{keycode: Some(Keycode::F6), repeat: false, ..} => Some(InputEvent::NextTheme),
+                // F7 turns the phosphor fade on and off
+                Event::KeyDown{keycode: Some(Keycode::F7), repeat: false, ..} => Some(InputEvent::TogglePhosphor),
+                // F10 starts and stops recording a clip
+                Event::KeyDown{keycode: Some(Keycode::F10), repeat: false, ..} => Some(InputEvent::ToggleCapture),
+                // P pauses, backspace rewinds
+                Event::KeyDown{keycode: Some(Keycode::P), repeat: false, ..} => Some(InputEvent::TogglePause),
+                Event::KeyDown{keycode: Some(Keycode::Backspace), repeat: false, ..} => Some(InputEvent::Rewind(true)),
+                Event::KeyUp{keycode: Some(Keycode::Backspace), ..} => Some(InputEvent::Rewind(false)),
+                Event::KeyDown{keycode: Some(key), ..} => self.keymap.get(key).map(InputEvent::KeyDown),
+                Event::KeyUp{keycode: Some(key), ..} => self.keymap.get(key).map(InputEvent::KeyUp),
+                _ => None,
+            };
+            events.extend(event);
+        }
+        events
+    }
+}
+
+fn state_slot(key: Keycode) -> Option<u8> {
+    match key {
+        Keycode::F1 => Some(1),
+        Keycode::F2 => Some(2),
+        Keycode::F3 => Some(3),
+        Keycode::F4 => Some(4),
+        _ => None,
+    }
+}
+
+// Which host key presses which CHIP-8 key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keymap {
+    keys: HashMap<Keycode, u8>,
+}
+
+impl Keymap {
+    pub fn get(&self, key: Keycode) -> Option<u8> {
+        self.keys.get(&key).copied()
+    }
+
+    // Key map files have one `host key = chip-8 key` pair per line, using SDL key
+    // names and hex CHIP-8 keys, e.g. `Up = 5`. Lines starting with # are comments.
+    // CHIP-8 keys that aren't mentioned keep their default host key.
+    pub fn parse(text: &str) -> Result<Keymap, String> {
+        let mut keymap = Keymap::default();
+        let mut remapped = [false; CHIP_8_TOTAL_KEYS];
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: String| format!("line {}: {}", index + 1, message);
+            let (host, chip8) = line.split_once('=').ok_or_else(|| error("expected `host key = chip-8 key`".to_string()))?;
+            let (host, chip8) = (host.trim(), chip8.trim());
+            let host = Keycode::from_name(host).ok_or_else(|| error(format!("unknown key '{}'", host)))?;
+            let chip8 = u8::from_str_radix(chip8, 16).ok().filter(|&key| key < 16)
+                .ok_or_else(|| error(format!("invalid CHIP-8 key '{}'", chip8)))?;
+            // A CHIP-8 key given new host keys no longer answers to its default one
+            if !remapped[chip8 as usize] {
+                remapped[chip8 as usize] = true;
+                keymap.keys.retain(|_, &mut key| key != chip8);
+            }
+            keymap.keys.insert(host, chip8);
+        }
+        Ok(keymap)
+    }
+
+    // Also put the CHIP-8 keys a game uses for directions and buttons on the arrow
+    // keys, space and return, using key hints like ("up", 5) from the ROM database
+    pub fn add_hints(&mut self, hints: &BTreeMap<String, u8>) {
+        for (name, &chip8) in hints {
+            let host = match name.as_str() {
+                "up" => Keycode::Up,
+                "down" => Keycode::Down,
+                "left" => Keycode::Left,
+                "right" => Keycode::Right,
+                "a" => Keycode::Space,
+                "b" => Keycode::Return,
+                _ => continue,
+            };
+            if chip8 < 16 {
+                self.keys.insert(host, chip8);
+            }
+        }
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Keymap { keys: DEFAULT_KEYS.iter().copied().collect() }
+    }
+}
+
+/*
+    Keyboard                    Chip-8
+    +---+---+---+---+           +---+---+---+---+
+    | 1 | 2 | 3 | 4 |           | 1 | 2 | 3 | C |
+    +---+---+---+---+           +---+---+---+---+
+    | Q | W | E | R |           | 4 | 5 | 6 | D |
+    +---+---+---+---+     =>    +---+---+---+---+
+    | A | S | D | F |           | 7 | 8 | 9 | E |
+    +---+---+---+---+           +---+---+---+---+
+    | Z | X | C | V |           | A | 0 | B | F |
+    +---+---+---+---+           +---+---+---+---+
+*/
+const DEFAULT_KEYS: [(Keycode, u8); 16] = [
+    (Keycode::Num1, 0x1),
+    (Keycode::Num2, 0x2),
+    (Keycode::Num3, 0x3),
+    (Keycode::Num4, 0xC),
+    (Keycode::Q, 0x4),
+    (Keycode::W, 0x5),
+    (Keycode::E, 0x6),
+    (Keycode::R, 0xD),
+    (Keycode::A, 0x7),
+    (Keycode::S, 0x8),
+    (Keycode::D, 0x9),
+    (Keycode::F, 0xE),
+    (Keycode::Z, 0xA),
+    (Keycode::X, 0x0),
+    (Keycode::C, 0xB),
+    (Keycode::V, 0xF),
+];
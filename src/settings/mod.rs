@@ -0,0 +1,182 @@
+use std::collections::{BTreeMap, HashMap};
+use serde::Deserialize;
+use crate::platform::Platform;
+use crate::quirks::Quirks;
+use crate::romdb::RomInfo;
+use crate::config::CHIP_8_DEFAULT_PALETTE;
+use crate::palette::{overlay, parse_colour, Theme};
+
+// The user's settings file. Per-ROM entries are keyed by the ROM's SHA-1, as shown
+// by `chip-8 info`, and replace whatever the ROM database says:
+//
+//     database = "/home/me/chip-8-database/database/programs.json"
+//     theme = "amber"
+//     palette = ["#000000"]
+//
+//     [roms.0df2789f2e5c1ab8a7b6c1e4d5b4b4b4a0b1c2d3]
+//     title = "Pong"
+//     platform = "chip8"
+//     quirks = "vip"
+//     quirk = { shift = true }
+//     tickrate = 15
+//     theme = "green"
+//     palette = ["#000000", "#33ff66"]
+//     keys = { up = 1, down = 4 }
+#[derive(Debug, Clone)]
+pub struct Settings {
+    // The ROM database to use instead of the default one
+    pub database: Option<String>,
+    // Colours for every ROM, from `theme` with `palette` written over it
+    pub palette: [[u8; 3]; 4],
+    pub roms: HashMap<String, RomInfo>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            database: None,
+            palette: CHIP_8_DEFAULT_PALETTE,
+            roms: HashMap::new(),
+        }
+    }
+}
+
+impl Settings {
+    pub fn parse(text: &str) -> Result<Settings, String> {
+        let file: SettingsFile = toml::from_str(text).map_err(|e| e.to_string())?;
+        let mut roms = HashMap::new();
+        for (hash, rom) in file.roms {
+            let info = rom.into_info().map_err(|e| format!("roms.{}: {}", hash, e))?;
+            roms.insert(hash.to_ascii_lowercase(), info);
+        }
+        let palette = theme_palette(file.theme.as_deref(), &file.palette)?;
+        let mut colours = CHIP_8_DEFAULT_PALETTE;
+        overlay(&mut colours, &palette);
+        Ok(Settings { database: file.database, palette: colours, roms })
+    }
+
+    pub fn rom(&self, hash: &str) -> Option<&RomInfo> {
+        self.roms.get(hash)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SettingsFile {
+    database: Option<String>,
+    theme: Option<String>,
+    #[serde(default)]
+    palette: Vec<String>,
+    #[serde(default)]
+    roms: BTreeMap<String, RomSettings>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RomSettings {
+    title: Option<String>,
+    platform: Option<String>,
+    quirks: Option<String>,
+    #[serde(default)]
+    quirk: BTreeMap<String, bool>,
+    tickrate: Option<u32>,
+    theme: Option<String>,
+    #[serde(default)]
+    palette: Vec<String>,
+    #[serde(default)]
+    keys: BTreeMap<String, u8>,
+}
+
+impl RomSettings {
+    fn into_info(self) -> Result<RomInfo, String> {
+        let platform = match self.platform {
+            Some(name) => Some(Platform::from_name(&name).ok_or(format!("unknown platform '{}'", name))?),
+            None => None,
+        };
+        let quirks = match self.quirks {
+            Some(name) => Some(Quirks::preset(&name).ok_or(format!("unknown quirks preset '{}'", name))?),
+            None => None,
+        };
+        if let Some(name) = self.quirk.keys().find(|name| Quirks::default().get(name).is_none()) {
+            return Err(format!("unknown quirk '{}'", name));
+        }
+        if self.tickrate == Some(0) {
+            return Err("tickrate must be at least 1".to_string());
+        }
+        let palette = theme_palette(self.theme.as_deref(), &self.palette)?;
+        if let Some((name, key)) = self.keys.iter().find(|&(_, &key)| key > 0xF) {
+            return Err(format!("invalid key {} for '{}'", key, name));
+        }
+        Ok(RomInfo {
+            title: self.title,
+            platform,
+            quirks,
+            quirk_overrides: self.quirk,
+            tickrate: self.tickrate,
+            palette,
+            keys: self.keys,
+        })
+    }
+}
+
+// A theme's colours with the ones listed written over them, or just the listed ones
+fn theme_palette(theme: Option<&str>, colours: &[String]) -> Result<Vec<[u8; 3]>, String> {
+    let colours = colours.iter()
+        .map(|colour| parse_colour(colour).ok_or(format!("invalid colour '{}'", colour)))
+        .collect::<Result<Vec<_>, _>>()?;
+    if colours.len() > 4 {
+        return Err("a palette has at most 4 colours".to_string());
+    }
+    match theme {
+        Some(name) => {
+            let mut palette = Theme::from_name(name).ok_or(format!("unknown theme '{}'", name))?.colours;
+            overlay(&mut palette, &colours);
+            Ok(palette.to_vec())
+        },
+        None => Ok(colours),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SETTINGS: &str = r##"
+        database = "/home/me/chip-8-database/database/programs.json"
+        theme = "amber"
+
+        [roms.0DF2789F2E5C1AB8A7B6C1E4D5B4B4B4A0B1C2D3]
+        title = "Pong"
+        platform = "schip"
+        quirks = "vip"
+        quirk = { shift = true, vf_reset = false }
+        tickrate = 15
+        palette = ["#000000", "#33ff66"]
+        keys = { up = 1, down = 4 }
+    "##;
+
+    #[test]
+    fn settings_file_resolves_to_quirks() {
+        let settings = Settings::parse(SETTINGS).unwrap();
+        assert_eq!(settings.database.as_deref(), Some("/home/me/chip-8-database/database/programs.json"));
+        assert_eq!(settings.palette, Theme::from_name("amber").unwrap().colours);
+
+        let info = settings.rom("0df2789f2e5c1ab8a7b6c1e4d5b4b4b4a0b1c2d3").unwrap();
+        assert_eq!(info.title.as_deref(), Some("Pong"));
+        assert_eq!(info.platform, Some(Platform::SuperChip));
+        assert_eq!(info.tickrate, Some(15));
+        assert_eq!(info.palette, [[0, 0, 0], [0x33, 0xff, 0x66]]);
+        assert_eq!(info.keys.get("down"), Some(&4));
+        let expected = Quirks { shift: true, vf_reset: false, ..Quirks::cosmac_vip() };
+        assert_eq!(info.resolve_quirks(Platform::SuperChip), expected);
+    }
+
+    #[test]
+    fn mistakes_are_reported() {
+        let unknown_quirk = "[roms.ab]\nquirk = { wobble = true }\n";
+        assert_eq!(Settings::parse(unknown_quirk).unwrap_err(), "roms.ab: unknown quirk 'wobble'");
+        let bad_key = "[roms.ab]\nkeys = { up = 16 }\n";
+        assert_eq!(Settings::parse(bad_key).unwrap_err(), "roms.ab: invalid key 16 for 'up'");
+        assert!(Settings::parse("colour = \"red\"\n").is_err());
+    }
+}
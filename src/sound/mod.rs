@@ -1,22 +1,98 @@
 extern crate rodio;
 
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
-use rodio::{OutputStream, Sink, source::SineWave, Source};
-use rodio::source::TakeDuration;
-
-// Sound function: Generates a beep sound at the specified frequency and duration
-pub fn beep(frequency: u32, duration_ms: u64) -> Result<(), std::io::Error> {
-    // Create an audio output stream
-    let (_stream, stream_handle) = OutputStream::try_default()
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))?;
+use rodio::{OutputStream, Sink, Source};
+use crate::config::CHIP_8_AUDIO_SAMPLE_RATE;
+use crate::frontend::{Audio, AudioSettings, Tone};
+use crate::synth::Synth;
+
+// Samples generated between checks for a new tone, about 1.5 ms
+const UPDATE_INTERVAL: u32 = 64;
+
+// Open the default output device. The stream must be kept alive while the sink plays.
+fn open_output() -> Result<(OutputStream, Sink), std::io::Error> {
+    let (stream, stream_handle) = OutputStream::try_default()
+        .map_err(|e| std::io::Error::other(e.to_string()))?;
     let sink = Sink::try_new(&stream_handle)
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))?;
+        .map_err(|e| std::io::Error::other(e.to_string()))?;
+    Ok((stream, sink))
+}
+
+// What the emulation thread wants played, read by the audio thread
+#[derive(Debug, Clone, Copy)]
+struct Shared {
+    tone: Option<Tone>,
+}
+
+// Endless source that plays whatever tone is currently requested, or silence
+struct ToneSource {
+    shared: Arc<Mutex<Shared>>,
+    synth: Synth,
+    countdown: u32,
+}
+
+impl Iterator for ToneSource {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.countdown == 0 {
+            self.countdown = UPDATE_INTERVAL;
+            // Never wait on the emulation thread, just pick the change up next time
+            if let Ok(shared) = self.shared.try_lock() {
+                self.synth.set_tone(shared.tone);
+            }
+        }
+        self.countdown -= 1;
+        Some(self.synth.next_sample())
+    }
+}
+
+impl Source for ToneSource {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        CHIP_8_AUDIO_SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+// Plays the sound timer through the default output device. The stream is opened
+// once and runs on rodio's thread, so updating the tone never blocks emulation. The
+// buzzer settings are fixed when the device is created.
+pub struct RodioAudio {
+    shared: Arc<Mutex<Shared>>,
+    _stream: OutputStream,
+    _sink: Sink,
+}
 
-    // Create a sine wave at the specified frequency
-    let source: TakeDuration<SineWave> = SineWave::new(frequency).take_duration(Duration::from_millis(duration_ms));
-    sink.append(source);
+impl RodioAudio {
+    pub fn new(settings: AudioSettings) -> Result<Self, std::io::Error> {
+        let (stream, sink) = open_output()?;
+        let shared = Arc::new(Mutex::new(Shared { tone: None }));
+        sink.append(ToneSource {
+            shared: Arc::clone(&shared),
+            synth: Synth::new(settings, CHIP_8_AUDIO_SAMPLE_RATE),
+            countdown: 0,
+        });
+        Ok(RodioAudio { shared, _stream: stream, _sink: sink })
+    }
+}
 
-    // Block until the sound has finished playing
-    sink.sleep_until_end();
-    Ok(())
-}
\ No newline at end of file
+impl Audio for RodioAudio {
+    fn set_tone(&mut self, tone: Option<Tone>) {
+        // The audio thread only holds the lock long enough to copy the state out
+        if let Ok(mut shared) = self.shared.lock() {
+            shared.tone = tone;
+        }
+    }
+}
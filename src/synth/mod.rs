@@ -0,0 +1,65 @@
+use crate::frontend::{AudioSettings, Tone};
+
+// Per sample change in level when starting and stopping, to avoid clicks
+const FADE_STEP: f32 = 1.0 / 256.0;
+
+// Turns the tone the sound timer asks for into samples. Shared by the audio output
+// and by clip recording, so a recording sounds like what was heard.
+#[derive(Debug, Clone, Copy)]
+pub struct Synth {
+    pub settings: AudioSettings,
+    sample_rate: u32,
+    // Last tone requested, which keeps sounding while it fades out
+    tone: Option<Tone>,
+    playing: bool,
+    // Position in the current waveform cycle or pattern, from 0 to 1
+    phase: f32,
+    level: f32,
+}
+
+impl Synth {
+    pub fn new(settings: AudioSettings, sample_rate: u32) -> Self {
+        Synth {
+            settings,
+            sample_rate,
+            tone: None,
+            playing: false,
+            phase: 0.0,
+            level: 0.0,
+        }
+    }
+
+    pub fn set_tone(&mut self, tone: Option<Tone>) {
+        self.playing = tone.is_some();
+        if tone.is_some() {
+            self.tone = tone;
+        }
+    }
+
+    pub fn next_sample(&mut self) -> f32 {
+        let settings = self.settings;
+        let sample_rate = self.sample_rate as f32;
+        let (sample, step) = match self.tone {
+            Some(Tone::Beep) => (settings.waveform.sample(self.phase), settings.frequency / sample_rate),
+            // XO-CHIP audio: the 128 bit pattern played back one bit at a time, at
+            // 4000 * 2^((pitch - 64) / 48) bits per second, as defined by Octo
+            Some(Tone::Pattern { pattern, pitch }) => {
+                let bits = pattern.len() * 8;
+                let position = ((self.phase * bits as f32) as usize).min(bits - 1);
+                let bit = (pattern[position / 8] >> (7 - position % 8)) & 1;
+                let rate = 4000.0 * 2f32.powf((pitch as f32 - 64.0) / 48.0);
+                (if bit == 1 { 1.0 } else { -1.0 }, rate / (bits as f32 * sample_rate))
+            },
+            None => (0.0, 0.0),
+        };
+        self.phase = (self.phase + step).fract();
+
+        let target = if self.playing { 1.0 } else { 0.0 };
+        if self.level < target {
+            self.level = (self.level + FADE_STEP).min(target);
+        } else if self.level > target {
+            self.level = (self.level - FADE_STEP).max(target);
+        }
+        sample * self.level * settings.volume
+    }
+}